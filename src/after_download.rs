@@ -0,0 +1,105 @@
+use std::fmt;
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use serde::{Serialize, Deserialize};
+
+use crate::logging::{status, Status};
+use crate::{capabilities, uid_set, ImapConfig, ImapSession};
+
+/// What happens on the server to an email once all its wanted attachments are saved.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum AfterDownload {
+    /// Leave the email as it is.
+    #[default]
+    None,
+    /// Set `\Seen`, e.g. together with `peek = true`.
+    MarkSeen,
+    /// Move the email to this folder, written as `move:<folder>`.
+    Move(String),
+    /// Delete the email; needs `--yes-really-delete` on the command line.
+    Delete,
+}
+
+impl TryFrom<String> for AfterDownload {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        match value.as_str() {
+            "none" => Ok(AfterDownload::None),
+            "mark-seen" => Ok(AfterDownload::MarkSeen),
+            "delete" => Ok(AfterDownload::Delete),
+            _ => match value.strip_prefix("move:") {
+                Some(folder) if !folder.trim().is_empty() => Ok(AfterDownload::Move(folder.trim().to_string())),
+                _ => Err(format!("unknown after_download \"{}\", expected none, mark-seen, move:<folder> or delete", value)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AfterDownload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AfterDownload::None => write!(f, "none"),
+            AfterDownload::MarkSeen => write!(f, "mark-seen"),
+            AfterDownload::Move(folder) => write!(f, "move:{}", folder),
+            AfterDownload::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+impl From<AfterDownload> for String {
+    fn from(value: AfterDownload) -> String {
+        value.to_string()
+    }
+}
+
+/// Sets `\Deleted` on the emails and expunges only them with UID EXPUNGE. Needs UIDPLUS, as a
+/// plain EXPUNGE would also remove every other email the user marked `\Deleted` in the folder.
+async fn expunge(imap_session: &mut ImapSession, uids: &str) -> Result<()> {
+    let _: Vec<_> = imap_session.uid_store(uids, "+FLAGS.SILENT (\\Deleted)").await?.try_collect().await?;
+    let _: Vec<_> = imap_session.uid_expunge(uids).await?.try_collect().await?;
+    Ok(())
+}
+
+/// Applies `after_download` to emails of the selected folder whose attachments were saved.
+pub async fn apply(imap_session: &mut ImapSession, config: &ImapConfig, uids: &[u32]) -> Result<()> {
+    // An interactive pick is about attachments, not about cleaning up the mailbox
+    if uids.is_empty() || config.selection.is_some() {
+        return Ok(());
+    }
+    // Checked here too, as the Windows service and daemon reloads start without `at_startup`
+    if config.after_download == AfterDownload::Delete && !config.confirm_delete {
+        return Err(anyhow!("after_download = \"delete\" needs --yes-really-delete, emails were left on the server"));
+    }
+    let set = uid_set(uids);
+
+    match &config.after_download {
+        AfterDownload::None => {}
+        AfterDownload::MarkSeen => {
+            let _: Vec<_> = imap_session.uid_store(&set, "+FLAGS.SILENT (\\Seen)").await?.try_collect().await?;
+        }
+        AfterDownload::Move(folder) => {
+            // MOVE (RFC 6851) is atomic; without it the copy is made before the originals are removed
+            if capabilities::has(imap_session, config, "MOVE").await? {
+                imap_session.uid_mv(&set, folder).await?;
+            } else if capabilities::has(imap_session, config, "UIDPLUS").await? {
+                imap_session.uid_copy(&set, folder).await?;
+                expunge(imap_session, &set).await?;
+            } else {
+                return Err(anyhow!("the server has neither MOVE nor UIDPLUS, so emails cannot be moved without \
+                    expunging other deleted emails too; they were left in place"));
+            }
+            status!(Status::Info, "moved {} emails to {}", uids.len(), folder);
+        }
+        AfterDownload::Delete => {
+            if !capabilities::has(imap_session, config, "UIDPLUS").await? {
+                return Err(anyhow!("the server lacks UIDPLUS, so emails cannot be deleted without expunging \
+                    other deleted emails too; they were left in place"));
+            }
+            expunge(imap_session, &set).await?;
+            status!(Status::Info, "deleted {} emails", uids.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+use anyhow::Result;
+
+/// Most bytes taken out of one archive.
+const MAX_TOTAL: u64 = 1 << 30;
+
+/// A file taken out of an archive, named by its path inside the archive.
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Archive formats recognised by their first bytes, not by the attachment's name.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Zip,
+    TarGz,
+}
+
+pub fn detect(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"PK\x03\x04") {
+        Some(Format::Zip)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        Some(Format::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Keeps only names that stay below the target directory: no absolute paths, drive letters or `..`.
+fn relative_name(path: &Path) -> Option<String> {
+    let parts: Vec<_> = path.components()
+        .map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            Component::CurDir => Some(String::new()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Reads every regular file of the archive into memory. Entries larger than `max_size` are
+/// left out, and reading stops after 1 GiB so a zip bomb cannot fill the disk.
+pub fn entries(data: &[u8], format: Format, max_size: Option<u64>) -> Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut total = 0;
+    let mut take = |name: Option<String>, size: u64, reader: &mut dyn Read| -> Result<bool> {
+        let Some(name) = name else { return Ok(true) };
+        if max_size.is_some_and(|max_size| size > max_size) {
+            return Ok(true);
+        }
+        total += size;
+        if total > MAX_TOTAL {
+            return Ok(false);
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        reader.take(size).read_to_end(&mut data)?;
+        entries.push(ArchiveEntry { name, data });
+        Ok(true)
+    };
+
+    match format {
+        Format::Zip => {
+            let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+            for index in 0..archive.len() {
+                let mut file = archive.by_index(index)?;
+                if !file.is_file() {
+                    continue;
+                }
+                let name = file.enclosed_name().as_deref().and_then(relative_name);
+                let size = file.size();
+                if !take(name, size, &mut file)? {
+                    break;
+                }
+            }
+        }
+        Format::TarGz => {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(data));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = relative_name(&entry.path()?);
+                let size = entry.size();
+                if !take(name, size, &mut entry)? {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,45 @@
+use mailparse::{DispositionType, ParsedMail};
+use serde::{Serialize, Deserialize};
+
+/// Which parts of the message text are saved next to the attachments as `{uid}-body.txt/html`.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SaveBody {
+    #[default]
+    None,
+    Txt,
+    Html,
+    Both,
+}
+
+impl SaveBody {
+    /// MIME types to look for, with the extension each is saved under.
+    fn types(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            SaveBody::None => &[],
+            SaveBody::Txt => &[("text/plain", "txt")],
+            SaveBody::Html => &[("text/html", "html")],
+            SaveBody::Both => &[("text/plain", "txt"), ("text/html", "html")],
+        }
+    }
+}
+
+/// The first part of type `mimetype` that is message text rather than an attached file.
+fn find_part<'a>(part: &'a ParsedMail<'a>, mimetype: &str) -> Option<&'a ParsedMail<'a>> {
+    if part.subparts.is_empty() {
+        let attached = matches!(part.get_content_disposition().disposition, DispositionType::Attachment);
+        return (part.ctype.mimetype.eq_ignore_ascii_case(mimetype) && !attached).then_some(part);
+    }
+    part.subparts.iter().find_map(|subpart| find_part(subpart, mimetype))
+}
+
+/// The selected body texts of a message as `(extension, UTF-8 text)`, decoded from their
+/// transfer encoding and charset.
+pub fn extract(parsed: &ParsedMail<'_>, save_body: SaveBody) -> Vec<(&'static str, String)> {
+    save_body.types().iter()
+        .filter_map(|(mimetype, extension)| {
+            let text = find_part(parsed, mimetype)?.get_body().ok()?;
+            Some((*extension, text))
+        })
+        .collect()
+}
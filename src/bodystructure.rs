@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use async_imap::imap_proto::types::{BodyContentCommon, BodyContentSinglePart, BodyStructure, ContentEncoding};
+
+use crate::filenames;
+
+/// A leaf part described by BODYSTRUCTURE, without its content.
+#[derive(Debug)]
+pub struct PartInfo {
+    /// Section path as used in `BODY[1.2]`.
+    pub section: Vec<u32>,
+    /// Lowercase `type/subtype`.
+    pub content_type: String,
+    pub filename: Option<String>,
+    pub content_id: Option<String>,
+    /// Lowercase `Content-Disposition` type, e.g. `attachment` or `inline`.
+    pub disposition: Option<String>,
+    pub encoding: String,
+    /// Size on the wire, i.e. still transfer-encoded.
+    pub octets: u32,
+}
+
+impl PartInfo {
+    pub fn section_string(&self) -> String {
+        self.section.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+    }
+
+    /// Approximate decoded size; base64 inflates content by a third.
+    pub fn decoded_size(&self) -> u64 {
+        match self.encoding.as_str() {
+            "base64" => self.octets as u64 * 3 / 4,
+            _ => self.octets as u64,
+        }
+    }
+}
+
+fn param(params: &Option<Vec<(Cow<'_, str>, Cow<'_, str>)>>, name: &str) -> Option<String> {
+    filenames::mime_param(params.as_ref()?, name)
+}
+
+fn encoding_name(encoding: &ContentEncoding<'_>) -> String {
+    match encoding {
+        ContentEncoding::SevenBit => "7bit".to_string(),
+        ContentEncoding::EightBit => "8bit".to_string(),
+        ContentEncoding::Binary => "binary".to_string(),
+        ContentEncoding::Base64 => "base64".to_string(),
+        ContentEncoding::QuotedPrintable => "quoted-printable".to_string(),
+        ContentEncoding::Other(other) => other.to_lowercase(),
+    }
+}
+
+fn leaf(section: &[u32], common: &BodyContentCommon<'_>, other: &BodyContentSinglePart<'_>) -> PartInfo {
+    let filename = param(&common.ty.params, "name")
+        .or_else(|| common.disposition.as_ref().and_then(|d| param(&d.params, "filename")));
+
+    PartInfo {
+        section: section.to_vec(),
+        content_type: format!("{}/{}", common.ty.ty, common.ty.subtype).to_lowercase(),
+        filename,
+        content_id: other.id.as_ref().map(|id| id.trim_matches(|c| c == '<' || c == '>').to_string()),
+        disposition: common.disposition.as_ref().map(|d| d.ty.to_lowercase()),
+        encoding: encoding_name(&other.transfer_encoding),
+        octets: other.octets,
+    }
+}
+
+fn walk(structure: &BodyStructure<'_>, section: &[u32], parts: &mut Vec<PartInfo>) {
+    match structure {
+        BodyStructure::Multipart { bodies, .. } => {
+            for (i, body) in bodies.iter().enumerate() {
+                let mut child = section.to_vec();
+                child.push(i as u32 + 1);
+                walk(body, &child, parts);
+            }
+        }
+        BodyStructure::Message { common, other, body, .. } => {
+            parts.push(leaf(section, common, other));
+            walk_message_body(body, section, parts);
+        }
+        BodyStructure::Basic { common, other, .. } | BodyStructure::Text { common, other, .. } => {
+            parts.push(leaf(section, common, other));
+        }
+    }
+}
+
+/// The body of a message is numbered like its parent: a multipart body's children get
+/// `section.N`, while a single-part body is `section.1`.
+fn walk_message_body(structure: &BodyStructure<'_>, section: &[u32], parts: &mut Vec<PartInfo>) {
+    match structure {
+        BodyStructure::Multipart { .. } => walk(structure, section, parts),
+        _ => {
+            let mut child = section.to_vec();
+            child.push(1);
+            walk(structure, &child, parts);
+        }
+    }
+}
+
+/// Flattens a message's BODYSTRUCTURE into its leaf parts.
+pub fn parts(structure: &BodyStructure<'_>) -> Vec<PartInfo> {
+    let mut parts = Vec::new();
+    walk_message_body(structure, &[], &mut parts);
+    parts
+}
+
@@ -0,0 +1,34 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_imap::types::Capability;
+
+use crate::{ImapConfig, ImapSession};
+
+/// Capability atoms of each account's server, asked for once per run: after login they are the
+/// same for every session, and asking before every search or batch costs a round trip each time.
+static KNOWN: Mutex<Option<HashMap<String, HashSet<String>>>> = Mutex::new(None);
+
+/// Whether the server of `config`'s account announces `capability`, such as `"X-GM-EXT-1"`.
+pub async fn has(imap_session: &mut ImapSession, config: &ImapConfig, capability: &str) -> Result<bool> {
+    let account = format!("{}@{}:{}", config.email, config.server, config.port());
+    let capability = capability.to_ascii_uppercase();
+
+    let cached = KNOWN.lock().unwrap().as_ref()
+        .and_then(|known| known.get(&account))
+        .map(|atoms| atoms.contains(&capability));
+    if let Some(has) = cached {
+        return Ok(has);
+    }
+
+    let atoms: HashSet<String> = imap_session.capabilities().await?.iter()
+        .filter_map(|capability| match capability {
+            Capability::Atom(atom) => Some(atom.to_ascii_uppercase()),
+            _ => None,
+        })
+        .collect();
+    let has = atoms.contains(&capability);
+    KNOWN.lock().unwrap().get_or_insert_with(HashMap::new).insert(account, atoms);
+    Ok(has)
+}
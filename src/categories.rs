@@ -0,0 +1,133 @@
+use serde::{Serialize, Deserialize};
+
+/// Coarse kind of attachment, selected with `categories` in config.toml.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    Images,
+    Documents,
+    Archives,
+    Audio,
+    Video,
+}
+
+const DOCUMENT_TYPES: &[&str] = &[
+    "application/pdf",
+    "application/msword",
+    "application/vnd.ms-excel",
+    "application/vnd.ms-powerpoint",
+    "application/rtf",
+    "application/epub+zip",
+    "text/plain",
+    "text/csv",
+    "text/rtf",
+];
+
+const DOCUMENT_PREFIXES: &[&str] = &[
+    "application/vnd.openxmlformats-officedocument.",
+    "application/vnd.oasis.opendocument.",
+    "application/vnd.ms-word.",
+    "application/vnd.ms-excel.",
+    "application/vnd.ms-powerpoint.",
+];
+
+const ARCHIVE_TYPES: &[&str] = &[
+    "application/zip",
+    "application/x-zip-compressed",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/x-tar",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+];
+
+impl Category {
+    /// `mime` is the bare `type/subtype`, lowercase and without parameters.
+    fn matches(self, mime: &str) -> bool {
+        match self {
+            // Some clients send JPEGs as `application/jpeg` or `image/jpg`
+            Category::Images => mime.starts_with("image/") || mime.ends_with("/jpeg") || mime.ends_with("/jpg"),
+            Category::Documents => {
+                DOCUMENT_TYPES.contains(&mime) || DOCUMENT_PREFIXES.iter().any(|prefix| mime.starts_with(prefix))
+            }
+            Category::Archives => ARCHIVE_TYPES.contains(&mime),
+            Category::Audio => mime.starts_with("audio/") || mime == "application/ogg",
+            Category::Video => mime.starts_with("video/"),
+        }
+    }
+}
+
+pub fn default_categories() -> Vec<Category> {
+    vec![Category::Images]
+}
+
+/// Matches a bare MIME type against `*`, `type/*` or an exact `type/subtype`.
+fn matches_pattern(mime: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+
+    if pattern == "*" {
+        return true;
+    }
+
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime.split('/').next() == Some(top_level),
+        None => mime == pattern,
+    }
+}
+
+/// Content types by file extension, for files that come without a `Content-Type` of their own,
+/// such as those taken out of `winmail.dat` or uuencoded in a message body.
+const EXTENSION_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("webp", "image/webp"),
+    ("heic", "image/heic"),
+    ("pdf", "application/pdf"),
+    ("doc", "application/msword"),
+    ("xls", "application/vnd.ms-excel"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("rtf", "application/rtf"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("zip", "application/zip"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "application/ogg"),
+    ("mp4", "video/mp4"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+];
+
+/// Guesses the content type from the file name, `application/octet-stream` when unknown.
+pub fn type_from_filename(filename: &str) -> &'static str {
+    let extension = filename.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).unwrap_or_default();
+    EXTENSION_TYPES.iter()
+        .find(|(known, _)| *known == extension)
+        .map_or("application/octet-stream", |(_, content_type)| content_type)
+}
+
+/// Whether a `Content-Type` value (parameters allowed) falls into any of the selected
+/// categories or matches any of the `attachment_types` patterns.
+pub fn is_wanted(content_type: &str, categories: &[Category], types: &[String]) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    categories.iter().any(|category| category.matches(&mime))
+        || types.iter().any(|pattern| matches_pattern(&mime, pattern))
+}
@@ -0,0 +1,47 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+/// File in `download_dir` that lists every saved attachment with `checksums = "sha256sums"`.
+pub const SUMS_FILE: &str = "SHA256SUMS";
+
+/// Whether saved attachments get checksum files, in the format `sha256sum -c` reads.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Checksums {
+    #[default]
+    None,
+    /// A `<file>.sha256` next to every saved attachment.
+    Sidecar,
+    /// One line per saved attachment in `SHA256SUMS` at the top of `download_dir`.
+    Sha256sums,
+}
+
+/// Attachments are saved in parallel, so appends to `SHA256SUMS` take turns.
+static SUMS: Mutex<()> = Mutex::new(());
+
+/// Records the checksum of the file saved at `path` below `download_dir`.
+pub fn write(checksums: Checksums, download_dir: &Path, path: &Path, sha256: &str) -> Result<()> {
+    match checksums {
+        Checksums::None => {}
+        Checksums::Sidecar => {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(".sha256");
+            std::fs::write(sidecar, format!("{}  {}\n", sha256, name))?;
+        }
+        Checksums::Sha256sums => {
+            let relative = path.strip_prefix(download_dir).unwrap_or(path);
+            // sha256sum expects forward slashes on every platform
+            let relative = relative.to_string_lossy().replace('\\', "/");
+
+            let _turn = SUMS.lock().unwrap();
+            let mut file = OpenOptions::new().create(true).append(true).open(download_dir.join(SUMS_FILE))?;
+            writeln!(file, "{}  {}", sha256, relative)?;
+        }
+    }
+    Ok(())
+}
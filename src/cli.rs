@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+use chrono::NaiveDate;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::Shell;
+
+use gmail_file_downloader::estimate::ByteSize;
+use gmail_file_downloader::events::EventFormat;
+use gmail_file_downloader::logging::LogFormat;
+use gmail_file_downloader::paths;
+
+#[derive(Parser)]
+#[command(version, about = "Downloads email attachments from a sender over IMAP")]
+pub struct Cli {
+    /// Suppress console output (log files are still written)
+    #[arg(long, global = true)]
+    pub quiet: bool,
+
+    /// Log diagnostics with spans per email and attachment to stderr: -v for debug, -vv for trace
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Write status lines and diagnostics as text or as one JSON object per line
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// Log the raw IMAP commands and responses, with credentials hidden
+    #[arg(long, global = true)]
+    pub imap_trace: bool,
+
+    /// Configuration file to use instead of ./config.toml or the one in the platform's config directory
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Account to log in as, overriding `email` in the config file
+    #[arg(long, global = true)]
+    pub email: Option<String>,
+
+    /// IMAP password; prefer the GMAIL_DOWNLOADER_PASSWORD variable, as flags show up in process lists
+    #[arg(long, global = true)]
+    pub password: Option<String>,
+
+    /// Read the IMAP password from a file containing only the password
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "password")]
+    pub password_file: Option<PathBuf>,
+
+    /// Download attachments from or to this address, overriding `sender`; may be repeated
+    #[arg(long, global = true)]
+    pub sender: Vec<String>,
+
+    /// IMAP server, overriding `server`
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+
+    /// Directory to save attachments to, overriding `download_dir`
+    #[arg(long, global = true, value_name = "DIR")]
+    pub out: Option<PathBuf>,
+
+    /// Fail instead of asking interactively for missing settings or credentials
+    #[arg(long, global = true)]
+    pub no_prompt: bool,
+
+    /// Emit machine-readable events on stdout; human-readable output moves to stderr
+    #[arg(long, global = true, value_enum)]
+    pub events: Option<EventFormat>,
+
+    /// Also scan the Spam folder, e.g. to recover a mis-filed attachment
+    #[arg(long, global = true)]
+    pub include_spam: bool,
+
+    /// Also scan the Trash folder, e.g. to recover an attachment from a deleted email
+    #[arg(long, global = true)]
+    pub include_trash: bool,
+
+    /// Only download from emails received on or after this date (YYYY-MM-DD)
+    #[arg(long, global = true, value_name = "DATE")]
+    pub since: Option<NaiveDate>,
+
+    /// Only download from emails received before this date (YYYY-MM-DD)
+    #[arg(long, global = true, value_name = "DATE")]
+    pub before: Option<NaiveDate>,
+
+    /// Skip attachments smaller than this, e.g. `100KB`
+    #[arg(long, global = true, value_name = "SIZE")]
+    pub min_size: Option<ByteSize>,
+
+    /// Skip attachments larger than this, e.g. `25MB`
+    #[arg(long, global = true, value_name = "SIZE")]
+    pub max_size: Option<ByteSize>,
+
+    /// Comma-separated MIME types to download, e.g. `image/*,application/pdf` or `*` for everything
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub types: Option<Vec<String>>,
+
+    /// Search all emails again instead of only those new or changed since the last run
+    #[arg(long, global = true)]
+    pub full: bool,
+
+    /// Save attachments whose transfer encoding cannot be decoded as they are instead of skipping them
+    #[arg(long, global = true)]
+    pub assume_binary: bool,
+
+    /// Append one JSON line per saved attachment to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// State database to use instead of `state.sqlite` in the state directory
+    #[arg(long, global = true, value_name = "FILE")]
+    pub db_path: Option<PathBuf>,
+
+    /// Only list the attachments that would be downloaded, without writing anything
+    #[arg(long, global = true, conflicts_with = "watch")]
+    pub dry_run: bool,
+
+    /// Allow `after_download = "delete"` to remove emails from the server
+    #[arg(long, global = true)]
+    pub yes_really_delete: bool,
+
+    /// List the matching attachments and pick the ones to download before any is fetched
+    #[arg(long, global = true, conflicts_with_all = ["dry_run", "watch", "all_accounts", "no_prompt"])]
+    pub interactive: bool,
+
+    /// Keep running and download from every folder as new emails arrive
+    #[arg(long, global = true)]
+    pub watch: bool,
+
+    /// After downloading, report what is new, changed or gone since the previous run
+    #[arg(long, global = true)]
+    pub diff: bool,
+
+    /// Use the `[[accounts]]` entry with this name from the config file
+    #[arg(long, global = true, value_name = "NAME", add = ArgValueCandidates::new(account_names))]
+    pub account: Option<String>,
+
+    /// Run every `[[accounts]]` entry of the config file, one after another
+    #[arg(long, global = true, conflicts_with = "account")]
+    pub all_accounts: bool,
+
+    /// With --all-accounts, run the accounts at the same time in separate processes
+    #[arg(long, global = true, requires = "all_accounts")]
+    pub concurrent: bool,
+
+    /// Keep watching in the background: writes a PID file, reloads the config on SIGHUP and stops cleanly on SIGTERM
+    #[cfg(unix)]
+    #[arg(long, conflicts_with_all = ["dry_run", "all_accounts"])]
+    pub daemon: bool,
+
+    /// PID file written by --daemon, by default `daemon.pid` in the state directory
+    #[cfg(unix)]
+    #[arg(long, value_name = "FILE", requires = "daemon")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Register the downloader as a Windows service
+    #[cfg(windows)]
+    #[arg(long)]
+    pub install_service: bool,
+
+    /// Remove the Windows service
+    #[cfg(windows)]
+    #[arg(long)]
+    pub uninstall_service: bool,
+
+    /// Entry point used by the service control manager
+    #[cfg(windows)]
+    #[arg(long, hide = true)]
+    pub service: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Download attachments (the default when no command is given)
+    Download,
+    /// Download everything once, then keep downloading from new emails as they arrive (same as --watch)
+    Watch,
+    /// List the account's folders with their attributes, marking those a run skips
+    ListFolders,
+    /// List the attachments that would be downloaded, without writing anything (same as --dry-run)
+    ListAttachments,
+    /// Create config.toml interactively, including picking the folders to search
+    Init,
+    /// Re-hash downloaded files against the history and state database and report missing or corrupted ones
+    Verify {
+        /// Re-download missing or corrupted files from their original messages
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Print how many emails and attachments of each folder the state database knows
+    Status,
+    /// Connect, log in, select a folder and search, reporting the timing of each stage
+    TestConnection,
+    /// Print the server's capabilities, namespaces, quota and special-use folders, warning about
+    /// missing capabilities the configuration relies on
+    Probe,
+    /// Create, edit, check or change config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print shell completions to stdout
+    Completions {
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Create config.toml interactively (same as `init`)
+    Init,
+    /// Open config.toml in $VISUAL or $EDITOR and save it only if it is still valid
+    Edit,
+    /// Check config.toml and its [[accounts]] entries: unknown keys, templates, conflicting options,
+    /// the server name and the download folder; exits with E_CONFIG on errors, for CI
+    Validate,
+    /// Print the value of a key
+    Get {
+        key: String,
+    },
+    /// Set a key, keeping the rest of the file and its comments intact
+    Set {
+        key: String,
+        value: String,
+    },
+    /// Encrypt the password with a master passphrase so config.toml can be committed safely
+    EncryptPassword,
+    /// Move the password from config.toml into the OS keychain
+    StorePassword,
+}
+
+impl Cli {
+    /// Whether folders are watched for new emails, with `watch`, --watch or --daemon.
+    pub fn watches(&self) -> bool {
+        #[cfg(unix)]
+        if self.daemon {
+            return true;
+        }
+        self.watch || matches!(self.command, Some(Command::Watch))
+    }
+
+    /// Whether attachments are only listed, with `list-attachments` or --dry-run.
+    pub fn lists_attachments(&self) -> bool {
+        self.dry_run || matches!(self.command, Some(Command::ListAttachments))
+    }
+}
+
+/// Names of the `[[accounts]]` entries in the default config file, offered when completing
+/// `--account`. Completion must never fail, so an unreadable file just offers nothing.
+fn account_names() -> Vec<CompletionCandidate> {
+    let Ok(text) = std::fs::read_to_string(paths::config_file(None)) else { return Vec::new() };
+    let Ok(document) = text.parse::<toml::Table>() else { return Vec::new() };
+    document.get("accounts").and_then(|accounts| accounts.as_array()).into_iter().flatten()
+        .filter_map(|account| account.get("name")?.as_str())
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn print_completions(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{Confirm, Password};
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+use crate::{credentials, secrets, validate, ImapConfig};
+
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Cannot read {:?}", path))?;
+    Ok(content.parse::<DocumentMut>()?)
+}
+
+fn lookup<'a>(document: &'a DocumentMut, key: &str) -> Option<&'a Item> {
+    let mut item = document.as_item();
+    for part in key.split('.') {
+        item = item.get(part)?;
+    }
+    Some(item)
+}
+
+/// Keys whose values `config set` does not echo, wherever they appear.
+const SECRET_KEYS: &[&str] = &["password", "password_encrypted", "client_secret", "access_key", "secret_key"];
+
+fn unknown_keys(document: &DocumentMut) -> Result<Vec<String>> {
+    Ok(validate::unknown_keys(&document.to_string().parse::<toml::Table>()?))
+}
+
+/// Values are taken as TOML when they parse as such (`10`, `true`, `["a", "b"]`)
+/// and as plain strings otherwise, so paths and addresses need no quoting.
+fn parse_value(raw: &str) -> Value {
+    raw.parse::<Value>().unwrap_or_else(|_| Value::from(raw))
+}
+
+pub fn get(path: &Path, key: &str) -> Result<()> {
+    let document = read_document(path)?;
+    let item = lookup(&document, key).ok_or_else(|| anyhow!("Key \"{}\" is not set in {:?}", key, path))?;
+
+    match item.as_str() {
+        Some(text) => println!("{}", text),
+        None => println!("{}", item.to_string().trim()),
+    }
+    Ok(())
+}
+
+/// Rewrites a single key in place, keeping comments and formatting of the rest of the file.
+/// The result must still load as a valid configuration, otherwise the file is left untouched.
+pub fn set(path: &Path, key: &str, raw_value: &str) -> Result<()> {
+    let mut document = read_document(path)?;
+    let unknown_before = unknown_keys(&document)?;
+
+    // Missing tables on the way are created; a value on the way is an error, not something to replace
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, parents) = parts.split_last().expect("split always yields a part");
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for part in parents {
+        table = table.entry(part)
+            .or_insert_with(|| {
+                let mut created = Table::new();
+                created.set_implicit(true);
+                Item::Table(created)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("Cannot set \"{}\": \"{}\" is not a table in {:?}", key, part, path))?;
+    }
+    let item = table.entry(last).or_insert(Item::None);
+
+    let decor = item.as_value().map(|value| value.decor().clone());
+    let mut value = parse_value(raw_value);
+    if let Some(decor) = decor {
+        *value.decor_mut() = decor;
+    }
+    *item = Item::Value(value);
+
+    // Typos elsewhere in the file are left to `config validate`; only a new unknown key is refused
+    let unknown: Vec<String> = unknown_keys(&document)?.into_iter()
+        .filter(|problem| !unknown_before.contains(problem))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!("{}", unknown.join("; "))).with_context(|| format!("Cannot set \"{}\"", key));
+    }
+
+    write_document(path, &document)
+        .with_context(|| format!("Invalid value for \"{}\"", key))?;
+    if SECRET_KEYS.contains(last) {
+        println!("{} = <redacted>", key);
+    } else {
+        println!("{} = {}", key, raw_value);
+    }
+    Ok(())
+}
+
+/// Validates the edited document as a configuration and atomically replaces the file with it.
+fn write_document(path: &Path, document: &DocumentMut) -> Result<()> {
+    let content = document.to_string();
+    toml::from_str::<ImapConfig>(&content).map_err(|e| anyhow!("{}", e.message()))?;
+
+    let tmp_path = path.with_extension("toml.tmp");
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// The editor from `VISUAL` or `EDITOR`, falling back to the platform's usual one.
+fn editor() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string())
+}
+
+/// Opens a copy of the file in an editor and replaces the file with it once it loads as a valid
+/// configuration; an invalid edit can be corrected or discarded.
+pub fn edit(path: &Path) -> Result<()> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Cannot read {:?}", path))?;
+    let tmp_path = path.with_extension("toml.edit");
+    std::fs::write(&tmp_path, &content)?;
+
+    // The editor may carry arguments, e.g. `code --wait`
+    let editor = editor();
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+
+    loop {
+        let status = std::process::Command::new(program).args(&args).arg(&tmp_path).status()
+            .with_context(|| format!("Cannot start the editor {:?}", editor))?;
+        if !status.success() {
+            std::fs::remove_file(&tmp_path)?;
+            return Err(anyhow!("The editor exited with {}; {:?} is unchanged", status, path));
+        }
+
+        let edited = std::fs::read_to_string(&tmp_path)?;
+        if edited == content {
+            std::fs::remove_file(&tmp_path)?;
+            println!("No changes");
+            return Ok(());
+        }
+        match toml::from_str::<ImapConfig>(&edited) {
+            Ok(_) => break,
+            Err(e) => {
+                eprintln!("The edited configuration is invalid: {}", e.message());
+                if !Confirm::new().with_prompt("Edit again?").default(true).interact()? {
+                    std::fs::remove_file(&tmp_path)?;
+                    return Err(anyhow!("Changes discarded; {:?} is unchanged", path));
+                }
+            }
+        }
+    }
+
+    std::fs::rename(&tmp_path, path)?;
+    println!("Saved {:?}", path);
+    Ok(())
+}
+
+/// Replaces the plaintext `password` with `password_encrypted`, asking for the password if none is stored.
+pub fn encrypt_password(path: &Path) -> Result<()> {
+    let mut document = read_document(path)?;
+
+    let password = match document.get("password").and_then(Item::as_str) {
+        Some(password) => password.to_string(),
+        None => Password::new().with_prompt("Password to encrypt").interact()?,
+    };
+    let passphrase = secrets::master_passphrase(false, true)?;
+
+    document["password_encrypted"] = toml_edit::value(secrets::encrypt(&password, &passphrase)?);
+    document.remove("password");
+
+    write_document(path, &document)?;
+    println!("Password encrypted; set {} to run without a prompt", secrets::PASSPHRASE_ENV);
+    Ok(())
+}
+
+/// Moves the password from config.toml (plaintext, encrypted or prompted for) into the OS keychain
+/// and removes it from the file.
+pub fn store_password(path: &Path) -> Result<()> {
+    let mut document = read_document(path)?;
+    let config: ImapConfig = toml::from_str(&document.to_string())?;
+
+    let password = match (&config.password, &config.password_encrypted) {
+        (Some(password), _) => password.clone(),
+        (None, Some(encrypted)) => secrets::decrypt(encrypted, &secrets::master_passphrase(false, false)?)?,
+        (None, None) => Password::new().with_prompt(format!("Password for {}", config.email)).interact()?,
+    };
+
+    credentials::keyring_entry(&config)?
+        .set_password(&password)
+        .context("Cannot store the password in the OS keychain")?;
+
+    document.remove("password");
+    document.remove("password_encrypted");
+    write_document(path, &document)?;
+    println!("Password stored in the OS keychain and removed from {:?}", path);
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use dialoguer::Password;
+
+use crate::errors::ErrorCode;
+use crate::logging::{info, status, Status};
+use crate::{secrets, ImapConfig};
+
+pub const PASSWORD_ENV: &str = "GMAIL_DOWNLOADER_PASSWORD";
+const KEYRING_SERVICE: &str = "gmail_file_downloader";
+
+/// Keychain entries are keyed by account and server so several accounts can coexist.
+pub fn keyring_entry(config: &ImapConfig) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, &format!("{}@{}", config.email, config.server))?)
+}
+
+/// The OAuth2 refresh token lives in its own entry next to the password.
+pub fn refresh_token_entry(config: &ImapConfig) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, &format!("oauth2:{}@{}", config.email, config.server))?)
+}
+
+pub fn read_entry(entry: &keyring::Entry) -> Result<Option<String>> {
+    match entry.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn from_keyring(config: &ImapConfig) -> Result<Option<String>> {
+    read_entry(&keyring_entry(config)?)
+}
+
+/// Moves a plaintext `password` into the OS keychain so it is not written to config.toml.
+/// Without a working keychain the password stays in the config and a warning is shown.
+pub fn move_to_keyring(config: &mut ImapConfig) {
+    let Some(password) = &config.password else { return };
+
+    match keyring_entry(config).and_then(|entry| Ok(entry.set_password(password)?)) {
+        Ok(()) => {
+            info!("-- Password stored in the OS keychain");
+            config.password = None;
+        }
+        Err(e) => status!(Status::Warning, "cannot use the OS keychain ({:#}), the password is saved in plaintext", e),
+    }
+}
+
+/// Reads a password stored alone in a file, ignoring the trailing newline editors add.
+pub fn read_password_file(path: &Path) -> Result<String> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read password file {:?}", path))
+        .context(ErrorCode::Config)?;
+    Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Resolves the password in order: CLI flag, environment variable, OS keychain,
+/// config file and finally an interactive prompt, unless `no_prompt` is set.
+pub fn resolve_password(config: &ImapConfig, cli_password: Option<String>, no_prompt: bool) -> Result<String> {
+    if let Some(password) = cli_password {
+        return Ok(password);
+    }
+
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+
+    // A broken keychain backend (e.g. no secret service on a headless box) should not block the other sources
+    if let Ok(Some(password)) = from_keyring(config) {
+        return Ok(password);
+    }
+
+    if let Some(password) = &config.password {
+        return Ok(password.clone());
+    }
+
+    if let Some(encrypted) = &config.password_encrypted {
+        let passphrase = secrets::master_passphrase(no_prompt, false)?;
+        return secrets::decrypt(encrypted, &passphrase);
+    }
+
+    if no_prompt {
+        bail!("No password found for {} (tried --password, {}, OS keychain and config.toml)", config.email, PASSWORD_ENV);
+    }
+
+    Ok(Password::new()
+        .with_prompt(format!("Password for {}", config.email))
+        .interact()?)
+}
@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use sd_notify::NotifyState;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Interval;
+
+use crate::errors::ErrorCode;
+use crate::logging::{info, status, Status};
+use crate::{shutdown, watch, ImapConfig};
+
+/// Set in the background process, so it does not detach once more.
+const DETACHED_VAR: &str = "GMAIL_DOWNLOADER_DETACHED";
+
+pub const PID_FILE: &str = "daemon.pid";
+
+/// Whether `--daemon` has to move to the background first: not under systemd, which
+/// supervises the process itself, and not in the process started for that.
+pub fn should_detach() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_none() && std::env::var_os(DETACHED_VAR).is_none()
+}
+
+/// Starts this program again with the same arguments in its own process group, without a
+/// terminal, and returns its PID.
+pub fn detach() -> Result<u32> {
+    use std::os::unix::process::CommandExt;
+
+    let child = std::process::Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(DETACHED_VAR, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    Ok(child.id())
+}
+
+/// Removed again when the daemon exits.
+struct PidFile(PathBuf);
+
+impl PidFile {
+    fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Cannot write PID file {:?}", path))
+            .context(ErrorCode::Io)?;
+        Ok(PidFile(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Half of systemd's `WatchdogSec`, if the unit sets one.
+fn watchdog_interval() -> Option<Interval> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec)
+        .then(|| tokio::time::interval(Duration::from_micros(usec / 2)))
+}
+
+async fn tick(watchdog: &mut Option<Interval>) {
+    match watchdog {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn notify(state: NotifyState<'_>) {
+    // Outside systemd there is nobody to tell
+    let _ = sd_notify::notify(false, &[state]);
+}
+
+/// Runs the watch loop until SIGTERM or SIGINT, reporting readiness and watchdog pings to
+/// systemd. SIGHUP restarts the loop with the configuration `reload` returns. Either way,
+/// emails already fetched are saved before the loop is dropped.
+pub async fn run<F, Fut>(mut config: ImapConfig, pid_file: &Path, reload: F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<ImapConfig>>,
+{
+    let _pid_file = PidFile::create(pid_file)?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut watchdog = watchdog_interval();
+
+    info!("-- Running as a daemon, PID {}", std::process::id());
+    notify(NotifyState::Ready);
+
+    loop {
+        let mut watch = Box::pin(watch::run(&config));
+        let reloading = loop {
+            tokio::select! {
+                result = &mut watch => return result,
+                _ = terminate.recv() => break false,
+                _ = interrupt.recv() => break false,
+                _ = hangup.recv() => break true,
+                _ = tick(&mut watchdog) => notify(NotifyState::Watchdog),
+            }
+        };
+
+        notify(if reloading { NotifyState::Reloading } else { NotifyState::Stopping });
+        info!("-- {}, finishing the emails in progress", if reloading { "Reloading" } else { "Stopping" });
+        shutdown::request();
+
+        // No new email is fetched now, but those already fetched are saved before every session logs out
+        loop {
+            tokio::select! {
+                _ = &mut watch => break,
+                _ = tick(&mut watchdog) => notify(NotifyState::Watchdog),
+            }
+        }
+        drop(watch);
+
+        if !reloading {
+            info!("-- Stopped");
+            return Ok(());
+        }
+
+        match reload().await {
+            Ok(reloaded) => {
+                config = reloaded;
+                info!("-- Configuration reloaded");
+            }
+            Err(e) => status!(Status::Warning, "cannot reload the configuration, keeping the current one: {:#}", e),
+        }
+        shutdown::reset();
+        notify(NotifyState::Ready);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::errors::ErrorCode;
+use crate::estimate::format_size;
+use crate::history::HistoryEntry;
+use crate::logging::info;
+use crate::MessageSource;
+
+pub const DB_FILE: &str = "state.sqlite";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        mailbox TEXT NOT NULL,
+        uid_validity INTEGER NOT NULL,
+        uid INTEGER NOT NULL,
+        processed_at TEXT NOT NULL,
+        PRIMARY KEY (mailbox, uid_validity, uid)
+    );
+    CREATE TABLE IF NOT EXISTS attachments (
+        mailbox TEXT NOT NULL,
+        uid_validity INTEGER NOT NULL,
+        uid INTEGER NOT NULL,
+        filename TEXT NOT NULL,
+        path TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        sha256 TEXT NOT NULL,
+        saved_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS attachments_sha256 ON attachments (sha256);
+";
+
+/// The state database of the running download, opened once and shared by every task.
+static DB: Mutex<Option<(PathBuf, Connection)>> = Mutex::new(None);
+
+/// Mailboxes without UIDVALIDITY are stored with 0, as NULLs would never collide in the primary key.
+fn uid_validity(source: &MessageSource) -> u32 {
+    source.uid_validity.unwrap_or(0)
+}
+
+/// Opens (creating it if needed) the database at `path`; a no-op when it is already open.
+pub fn open(path: &Path) -> Result<()> {
+    let mut db = DB.lock().unwrap();
+    if db.as_ref().is_some_and(|(open_path, _)| open_path == path) {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let connection = Connection::open(path)
+        .and_then(|connection| connection.execute_batch(SCHEMA).map(|_| connection))
+        .with_context(|| format!("Cannot open the state database {:?}", path))
+        .context(ErrorCode::Io)?;
+    *db = Some((path.to_path_buf(), connection));
+    Ok(())
+}
+
+fn with_db<T: Default>(f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T> {
+    match DB.lock().unwrap().as_ref() {
+        Some((_, connection)) => Ok(f(connection).context(ErrorCode::Io)?),
+        None => Ok(T::default()),
+    }
+}
+
+pub fn message_processed(source: &MessageSource) -> Result<()> {
+    with_db(|connection| {
+        connection.execute(
+            "INSERT OR REPLACE INTO messages (mailbox, uid_validity, uid, processed_at) VALUES (?1, ?2, ?3, ?4)",
+            params![source.mailbox, uid_validity(source), source.uid, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    })
+}
+
+pub fn attachment_saved(source: &MessageSource, filename: &str, path: &Path, size: u64, sha256: &str) -> Result<()> {
+    with_db(|connection| {
+        connection.execute(
+            "INSERT INTO attachments (mailbox, uid_validity, uid, filename, path, size, sha256, saved_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                source.mailbox, uid_validity(source), source.uid, filename,
+                path.to_string_lossy(), size, sha256, Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// UIDs of `mailbox` processed in earlier runs, as long as the server kept the same UIDVALIDITY.
+pub fn processed_uids(mailbox: &str, uid_validity: Option<u32>) -> Result<HashSet<u32>> {
+    with_db(|connection| {
+        let mut statement = connection.prepare("SELECT uid FROM messages WHERE mailbox = ?1 AND uid_validity = ?2")?;
+        let uids = statement.query_map(params![mailbox, uid_validity.unwrap_or(0)], |row| row.get(0))?;
+        uids.collect()
+    })
+}
+
+/// SHA-256 of every attachment ever saved, for `dedup`.
+pub fn hashes() -> Result<HashSet<String>> {
+    with_db(|connection| {
+        let mut statement = connection.prepare("SELECT DISTINCT sha256 FROM attachments")?;
+        let hashes = statement.query_map([], |row| row.get(0))?;
+        hashes.collect()
+    })
+}
+
+/// Every saved attachment, the latest save of each path last, for `verify`.
+pub fn attachments() -> Result<Vec<HistoryEntry>> {
+    with_db(|connection| {
+        let mut statement = connection.prepare(
+            "SELECT mailbox, uid_validity, uid, filename, path, size, sha256 FROM attachments ORDER BY saved_at",
+        )?;
+        let entries = statement.query_map([], |row| {
+            let uid_validity: u32 = row.get(1)?;
+            Ok(HistoryEntry {
+                mailbox: row.get(0)?,
+                uid_validity: (uid_validity != 0).then_some(uid_validity),
+                uid: row.get(2)?,
+                filename: row.get(3)?,
+                path: PathBuf::from(row.get::<_, String>(4)?),
+                size: row.get(5)?,
+                sha256: row.get(6)?,
+            })
+        })?;
+        entries.collect()
+    })
+}
+
+/// Prints per-folder totals from the database at `path`, for the `status` command.
+pub fn print_status(path: &Path) -> Result<()> {
+    let connection = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        Ok(connection) => connection,
+        Err(_) => {
+            info!("-- No state database at {:?} yet; it is created by the first download", path);
+            return Ok(());
+        }
+    };
+
+    let mut statement = connection.prepare("
+        SELECT m.mailbox, COUNT(*), MAX(m.processed_at),
+               (SELECT COUNT(*) FROM attachments a WHERE a.mailbox = m.mailbox),
+               (SELECT IFNULL(SUM(size), 0) FROM attachments a WHERE a.mailbox = m.mailbox)
+        FROM messages m GROUP BY m.mailbox ORDER BY m.mailbox
+    ").context(ErrorCode::Io)?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?, row.get::<_, String>(2)?, row.get::<_, u64>(3)?, row.get::<_, u64>(4)?))
+    }).context(ErrorCode::Io)?.collect::<rusqlite::Result<Vec<_>>>().context(ErrorCode::Io)?;
+
+    info!("{:<32}  {:>8}  {:>11}  {:>10}  {}", "FOLDER", "EMAILS", "ATTACHMENTS", "SIZE", "LAST PROCESSED");
+    let (mut messages, mut attachments, mut size) = (0, 0, 0);
+    for (mailbox, mailbox_messages, last_processed, mailbox_attachments, mailbox_size) in rows {
+        let last_processed = last_processed.get(..19).unwrap_or(&last_processed).replace('T', " ");
+        info!("{:<32}  {:>8}  {:>11}  {:>10}  {}", mailbox, mailbox_messages, mailbox_attachments, format_size(mailbox_size), last_processed);
+        messages += mailbox_messages;
+        attachments += mailbox_attachments;
+        size += mailbox_size;
+    }
+    info!("-- {} emails processed, {} attachments saved, {} in total", messages, attachments, format_size(size));
+    Ok(())
+}
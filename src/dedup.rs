@@ -0,0 +1,47 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// SHA-256 of every attachment saved so far, seeded from the history and the state database; `None` while dedup is off.
+static SEEN: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Set at the start of every run, so a run with dedup off does not inherit the hashes of an earlier one.
+pub fn init(saved: Option<HashSet<String>>) {
+    *SEEN.lock().unwrap() = saved;
+}
+
+/// Records `sha256` as saved, returning `false` if an attachment with that content was saved before.
+/// Always `true` when dedup is off. Claiming is atomic, so parallel saves of the same content keep one copy.
+pub fn claim(sha256: &str) -> bool {
+    match SEEN.lock().unwrap().as_mut() {
+        Some(seen) => seen.insert(sha256.to_string()),
+        None => true,
+    }
+}
+
+/// Forgets a claim whose save failed, so a later copy of the same content is still saved.
+pub fn release(sha256: &str) {
+    if let Some(seen) = SEEN.lock().unwrap().as_mut() {
+        seen.remove(sha256);
+    }
+}
+
+/// X-GM-MSGID or Message-ID of every email processed successfully so far in this run; `None` while message dedup is off.
+static MESSAGES: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Starts every run empty, and off unless `enabled`.
+pub fn init_messages(enabled: bool) {
+    *MESSAGES.lock().unwrap() = enabled.then(HashSet::new);
+}
+
+/// Records an email's identity once it has been processed, so its copies in other folders are skipped.
+pub fn message_processed(key: &str) {
+    if let Some(seen) = MESSAGES.lock().unwrap().as_mut() {
+        seen.insert(key.to_string());
+    }
+}
+
+/// Whether the same email was already processed from another folder in this run. Always `false`
+/// when message dedup is off.
+pub fn is_message_processed(key: &str) -> bool {
+    MESSAGES.lock().unwrap().as_ref().is_some_and(|seen| seen.contains(key))
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::Result;
+
+use crate::logging::{info, status, Status};
+use crate::report::{self, Manifest};
+
+/// Compares the two most recent run reports in `state_dir`.
+pub fn print_latest(state_dir: &Path) -> Result<()> {
+    let reports = report::list(state_dir)?;
+    let [.., previous, current] = reports.as_slice() else {
+        info!("-- No previous run to compare with");
+        return Ok(());
+    };
+
+    print(&report::load_manifest(previous)?, &report::load_manifest(current)?);
+    Ok(())
+}
+
+fn print(previous: &Manifest, current: &Manifest) {
+    info!("-- Changes since the run of {}", previous.started_at.format("%Y-%m-%d %H:%M:%S"));
+    let (mut new, mut changed, mut disappeared) = (0, 0, 0);
+
+    for (mailbox, manifest) in &current.mailboxes {
+        let Some(before) = previous.mailboxes.get(mailbox) else {
+            info!("  new folder {} with {} emails", mailbox, manifest.uids.len());
+            new += manifest.uids.len();
+            continue;
+        };
+
+        if before.uid_validity != manifest.uid_validity {
+            status!(Status::Warning, "UIDs of {} were reset by the server, cannot compare", mailbox);
+            continue;
+        }
+
+        for uid in manifest.uids.difference(&before.uids) {
+            info!("  new email #{} in {}", uid, mailbox);
+            new += 1;
+        }
+        for uid in before.uids.difference(&manifest.uids) {
+            status!(Status::Warning, "email #{} disappeared from {}", uid, mailbox);
+            disappeared += 1;
+        }
+    }
+
+    for (mailbox, before) in &previous.mailboxes {
+        if !current.mailboxes.contains_key(mailbox) {
+            status!(Status::Warning, "folder {} with {} emails was not searched this time", mailbox, before.uids.len());
+        }
+    }
+
+    // Reports written before hashes were recorded have an empty sha256 and are never "changed"
+    let previous_hashes: HashMap<_, _> = previous.files.iter()
+        .filter(|file| !file.sha256.is_empty())
+        .map(|file| (&file.path, &file.sha256))
+        .collect();
+
+    for file in &current.files {
+        if previous_hashes.get(&file.path).is_some_and(|sha256| **sha256 != file.sha256) {
+            status!(Status::Warning, "{:?} changed (email #{} in {})", file.path, file.uid, file.mailbox);
+            changed += 1;
+        }
+    }
+
+    info!("-- {} new, {} changed, {} disappeared", new, changed, disappeared);
+}
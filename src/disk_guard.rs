@@ -0,0 +1,43 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{anyhow, Result};
+
+use crate::errors::ErrorCode;
+use crate::estimate::format_size;
+use crate::logging::{status, Status};
+use crate::{shutdown, ImapConfig};
+
+/// Bytes of attachments written since the run started.
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+pub fn start() {
+    WRITTEN.store(0, Ordering::SeqCst);
+}
+
+pub fn saved(bytes: u64) {
+    WRITTEN.fetch_add(bytes, Ordering::SeqCst);
+}
+
+/// Checks `max_total_download` and `min_free_space` before `bytes` are written to `dir`. When a
+/// limit would be crossed the run is asked to stop, so no further email is fetched, and the save fails.
+pub fn check(config: &ImapConfig, dir: &Path, bytes: u64) -> Result<()> {
+    let written = WRITTEN.load(Ordering::SeqCst);
+    let exceeded = if config.max_total_download.is_some_and(|max| written + bytes > max.0) {
+        Some(format!("max_total_download is reached after {} written this run", format_size(written)))
+    } else if let Some(min_free) = config.min_free_space {
+        let available = fs2::available_space(dir)?;
+        (available < bytes + min_free.0)
+            .then(|| format!("only {} free in {:?}, below min_free_space", format_size(available), dir))
+    } else {
+        None
+    };
+
+    match exceeded {
+        Some(reason) => {
+            status!(Status::Warning, "stopping the run: {}", reason);
+            shutdown::request();
+            Err(anyhow!("Stopping: {}", reason).context(ErrorCode::DiskFull))
+        }
+        None => Ok(()),
+    }
+}
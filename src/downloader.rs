@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use anyhow::Result;
+use chrono::NaiveDate;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::{Stream, StreamExt};
+
+use crate::categories;
+use crate::estimate::ByteSize;
+use crate::{download_attachments, Category, ImapConfig};
+
+/// Which attachments to save.
+#[derive(Clone)]
+pub struct AttachmentFilter {
+    pub categories: Vec<Category>,
+    /// MIME patterns such as `application/pdf` or `image/*`, saved in addition to `categories`.
+    pub types: Vec<String>,
+    /// Filename globs such as `*.pdf`; when not empty, other names are skipped.
+    pub include: Vec<String>,
+    /// Filename globs such as `signature*` that are skipped.
+    pub exclude: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Skip inline parts such as signature logos.
+    pub attachments_only: bool,
+}
+
+impl Default for AttachmentFilter {
+    fn default() -> Self {
+        AttachmentFilter {
+            categories: categories::default_categories(),
+            types: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_size: None,
+            max_size: None,
+            attachments_only: false,
+        }
+    }
+}
+
+/// The settings needed to download from one account, without a config file.
+#[derive(Clone)]
+pub struct DownloadOptions {
+    pub email: String,
+    pub password: String,
+    pub server: String,
+    /// Addresses whose emails are searched, both sent by and to them.
+    pub senders: Vec<String>,
+    /// Mailbox names or globs; when empty the All Mail folder is found automatically.
+    pub folders: Vec<String>,
+    pub download_dir: PathBuf,
+    pub since: Option<NaiveDate>,
+    pub before: Option<NaiveDate>,
+    pub filter: AttachmentFilter,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        let config = ImapConfig::default();
+        DownloadOptions {
+            email: String::new(),
+            password: String::new(),
+            server: config.server,
+            senders: Vec::new(),
+            folders: Vec::new(),
+            download_dir: config.download_dir,
+            since: None,
+            before: None,
+            filter: AttachmentFilter::default(),
+        }
+    }
+}
+
+/// An attachment written to disk during a run.
+#[derive(Clone, Debug)]
+pub struct SavedAttachment {
+    pub mailbox: String,
+    pub uid: u64,
+    /// Name of the attachment in the email.
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+static SUBSCRIBER: Mutex<Option<UnboundedSender<SavedAttachment>>> = Mutex::new(None);
+
+/// Hands a saved attachment to the stream returned by `Downloader::attachments`, if one is listening.
+pub(crate) fn notify(saved: SavedAttachment) {
+    if let Some(sender) = SUBSCRIBER.lock().unwrap().as_ref() {
+        let _ = sender.unbounded_send(saved);
+    }
+}
+
+/// Downloads attachments like the command line tool does. Runs share process-wide state
+/// (run report, logging), so only one download should be in progress at a time.
+pub struct Downloader {
+    config: ImapConfig,
+}
+
+impl Downloader {
+    pub fn new(options: DownloadOptions) -> Self {
+        Downloader {
+            config: ImapConfig {
+                email: options.email,
+                password: Some(options.password),
+                server: options.server,
+                senders: options.senders,
+                folders: options.folders,
+                download_dir: options.download_dir,
+                since: options.since,
+                before: options.before,
+                categories: options.filter.categories,
+                attachment_types: options.filter.types,
+                include: options.filter.include,
+                exclude: options.filter.exclude,
+                min_size: options.filter.min_size.map(ByteSize),
+                max_size: options.filter.max_size.map(ByteSize),
+                attachments_only: options.filter.attachments_only,
+                ..ImapConfig::default()
+            },
+        }
+    }
+
+    /// Uses a full configuration, e.g. one read with `load_config`, with every option the CLI supports.
+    pub fn from_config(config: ImapConfig) -> Self {
+        Downloader { config }
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        download_attachments(&self.config).await
+    }
+
+    /// Runs the download, yielding every attachment as soon as it is saved. A failed run ends
+    /// the stream with its error.
+    pub fn attachments(&self) -> impl Stream<Item = Result<SavedAttachment>> + '_ {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let run = async move {
+            *SUBSCRIBER.lock().unwrap() = Some(sender);
+            let result = download_attachments(&self.config).await;
+            // Dropping the sender ends the receiving half once everything sent is consumed
+            SUBSCRIBER.lock().unwrap().take();
+            result
+        };
+        let failure = futures::stream::once(run).filter_map(|result| async move { result.err().map(Err::<SavedAttachment, _>) });
+
+        futures::stream::select(receiver.map(Ok), failure)
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use async_imap::imap_proto::types::Envelope;
+use futures::TryStreamExt;
+
+use crate::estimate::format_size;
+use crate::logging::info;
+use crate::sync::FolderState;
+use crate::{
+    bodystructure, is_candidate_part, part_filename, retry, search_uids, select_folders, uid_set, ImapConfig, ImapSession,
+};
+
+/// One attachment that a real run would save.
+pub(crate) struct Row {
+    pub uid: u32,
+    pub date: String,
+    pub sender: String,
+    pub subject: String,
+    pub filename: String,
+    pub size: u64,
+}
+
+/// The attachments a real run would save from one folder, and how many emails matched.
+pub(crate) struct FolderRows {
+    pub mailbox: String,
+    pub emails: usize,
+    pub rows: Vec<Row>,
+}
+
+fn envelope_date(envelope: &Envelope<'_>) -> String {
+    envelope.date.as_ref()
+        .and_then(|date| mailparse::dateparse(&String::from_utf8_lossy(date)).ok())
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+fn envelope_sender(envelope: &Envelope<'_>) -> String {
+    envelope.from.as_ref()
+        .and_then(|from| from.first())
+        .map(|address| {
+            let mailbox = address.mailbox.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+            let host = address.host.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+            format!("{}@{}", mailbox, host)
+        })
+        .unwrap_or_else(|| "?".to_string())
+}
+
+/// The subject as sent, with RFC 2047 encoded-words decoded.
+fn envelope_subject(envelope: &Envelope<'_>) -> String {
+    let raw = envelope.subject.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+    mailparse::parse_header(format!("Subject: {}", raw).as_bytes())
+        .map(|(header, _)| header.get_value())
+        .unwrap_or_else(|_| raw.into_owned())
+}
+
+/// Searches like a real run and reads the ENVELOPE and BODYSTRUCTURE of every match.
+/// No body is fetched and nothing is written.
+pub(crate) async fn collect(imap_session: &mut ImapSession, config: &ImapConfig) -> Result<Vec<FolderRows>> {
+    let mut folders = Vec::new();
+
+    for mailbox_name in select_folders(imap_session, config).await? {
+        let config = config.for_folder(&mailbox_name);
+        let mailbox = imap_session.select(&mailbox_name).await?;
+
+        // Only what the next incremental run would look at
+        let min_uid = FolderState::load(&FolderState::path_for(&config.state_dir, &mailbox_name))
+            .filter(|state| state.applies_to(&config, mailbox.uid_validity))
+            .and_then(|state| state.highest_uid)
+            .map(|highest_uid| highest_uid + 1);
+        let uids = config.order.sort(search_uids(imap_session, &config, None, min_uid).await?);
+
+        let mut rows = Vec::new();
+        for chunk in uids.chunks(500) {
+            let mut messages_stream = imap_session.uid_fetch(uid_set(chunk), "(UID ENVELOPE BODYSTRUCTURE)").await?;
+
+            while let Some(message) = messages_stream.try_next().await? {
+                let (Some(uid), Some(envelope), Some(structure)) = (message.uid, message.envelope(), message.bodystructure()) else { continue };
+
+                for part in bodystructure::parts(structure).iter().filter(|part| is_candidate_part(part, &config)) {
+                    let Some(filename) = part_filename(part) else { continue };
+                    rows.push(Row {
+                        uid,
+                        date: envelope_date(envelope),
+                        sender: envelope_sender(envelope),
+                        subject: envelope_subject(envelope),
+                        filename,
+                        size: part.decoded_size(),
+                    });
+                }
+            }
+        }
+
+        folders.push(FolderRows { mailbox: mailbox_name, emails: uids.len(), rows });
+    }
+
+    Ok(folders)
+}
+
+/// Prints what a real run would download.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    let mut imap_session = retry::connect(config).await?;
+    let mut total_files = 0;
+    let mut total_size = 0;
+
+    for folder in collect(&mut imap_session, config).await? {
+        info!("-- {}: {} attachments in {} emails", folder.mailbox, folder.rows.len(), folder.emails);
+        if !folder.rows.is_empty() {
+            info!("{:<10}  {:<32}  {:>10}  {}", "DATE", "SENDER", "SIZE", "FILENAME");
+        }
+        for row in &folder.rows {
+            info!("{:<10}  {:<32}  {:>10}  {}", row.date, row.sender, format_size(row.size), row.filename);
+            total_size += row.size;
+        }
+        total_files += folder.rows.len();
+    }
+
+    info!("-- Dry run: {} attachments, ~{} would be downloaded; nothing was written", total_files, format_size(total_size));
+    imap_session.logout().await?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io::ErrorKind;
+
+/// Stable failure categories. The codes and exit statuses are part of the CLI contract,
+/// so existing values must never change meaning; only new ones may be added.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    Config,
+    Connect,
+    Tls,
+    Auth,
+    FolderNotFound,
+    Parse,
+    DiskFull,
+    Io,
+    Imap,
+    Timeout,
+    Interrupted,
+    Other,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Config => "E_CONFIG",
+            ErrorCode::Connect => "E_CONNECT",
+            ErrorCode::Tls => "E_TLS",
+            ErrorCode::Auth => "E_AUTH",
+            ErrorCode::FolderNotFound => "E_FOLDER_NOT_FOUND",
+            ErrorCode::Parse => "E_PARSE",
+            ErrorCode::DiskFull => "E_DISK_FULL",
+            ErrorCode::Io => "E_IO",
+            ErrorCode::Imap => "E_IMAP",
+            ErrorCode::Timeout => "E_TIMEOUT",
+            ErrorCode::Interrupted => "E_INTERRUPTED",
+            ErrorCode::Other => "E_OTHER",
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCode::Other => 1,
+            ErrorCode::Config => 2,
+            ErrorCode::Connect => 3,
+            ErrorCode::Tls => 4,
+            ErrorCode::Auth => 5,
+            ErrorCode::FolderNotFound => 6,
+            ErrorCode::Parse => 7,
+            ErrorCode::DiskFull => 8,
+            ErrorCode::Io => 9,
+            ErrorCode::Imap => 10,
+            ErrorCode::Timeout => 11,
+            // What shells report for a process ended by Ctrl-C
+            ErrorCode::Interrupted => 130,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ErrorCode::Config => "invalid configuration",
+            ErrorCode::Connect => "cannot connect to the server",
+            ErrorCode::Tls => "TLS handshake failed",
+            ErrorCode::Auth => "authentication failed",
+            ErrorCode::FolderNotFound => "folder not found",
+            ErrorCode::Parse => "cannot parse message",
+            ErrorCode::DiskFull => "not enough disk space",
+            ErrorCode::Io => "file system error",
+            ErrorCode::Imap => "IMAP server error",
+            ErrorCode::Timeout => "timed out",
+            ErrorCode::Interrupted => "interrupted",
+            ErrorCode::Other => "unexpected error",
+        }
+    }
+}
+
+/// Used as anyhow context (`.context(ErrorCode::Auth)`) to tag an error where its cause is known.
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.description(), self.as_str())
+    }
+}
+
+/// Finds the code an error was tagged with, falling back to guessing from its source types.
+pub fn classify(error: &anyhow::Error) -> ErrorCode {
+    if let Some(code) = error.downcast_ref::<ErrorCode>() {
+        return *code;
+    }
+
+    for cause in error.chain() {
+        if let Some(code) = cause.downcast_ref::<ErrorCode>() {
+            return *code;
+        }
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            return match io_error.kind() {
+                ErrorKind::StorageFull => ErrorCode::DiskFull,
+                _ => ErrorCode::Io,
+            };
+        }
+        if cause.is::<toml::de::Error>() {
+            return ErrorCode::Config;
+        }
+        if cause.is::<mailparse::MailParseError>() {
+            return ErrorCode::Parse;
+        }
+        if cause.is::<async_imap::error::Error>() {
+            return ErrorCode::Imap;
+        }
+    }
+
+    ErrorCode::Other
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// A size in bytes, written either as a plain number or human-readable like `100KB` or `25 MB`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(try_from = "SizeValue", into = "u64")]
+pub struct ByteSize(pub u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<SizeValue> for ByteSize {
+    type Error = String;
+
+    fn try_from(value: SizeValue) -> Result<Self, String> {
+        match value {
+            SizeValue::Bytes(bytes) => Ok(ByteSize(bytes)),
+            SizeValue::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> u64 {
+        size.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    /// Units are decimal like `format_size` (`KB` = 1000 bytes); `KiB`, `MiB` and `GiB` are binary.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+
+        let number: f64 = number.parse().map_err(|_| format!("invalid size \"{}\"", text))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1_000,
+            "m" | "mb" => 1_000_000,
+            "g" | "gb" => 1_000_000_000,
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            other => return Err(format!("unknown size unit \"{}\" in \"{}\"", other, text)),
+        };
+
+        Ok(ByteSize((number * multiplier as f64).round() as u64))
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{} s", secs),
+        60..=3599 => format!("{} min", (secs + 30) / 60),
+        _ => format!("{} h {} min", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+/// Estimates the remaining time of a run from the bytes processed so far.
+pub struct Eta {
+    sizes: HashMap<u32, u64>,
+    total_bytes: u64,
+    done_bytes: u64,
+    started: Instant,
+}
+
+impl Eta {
+    pub fn new(sizes: HashMap<u32, u64>) -> Self {
+        let total_bytes = sizes.values().sum();
+        Eta {
+            sizes,
+            total_bytes,
+            done_bytes: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// RFC822.SIZE of every email of the run.
+    pub fn sizes(&self) -> &HashMap<u32, u64> {
+        &self.sizes
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn complete(&mut self, uids: &[u32]) {
+        self.done_bytes += uids.iter().filter_map(|uid| self.sizes.get(uid)).sum::<u64>();
+    }
+
+    /// `None` until some bytes have been processed and a speed can be measured.
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.done_bytes == 0 {
+            return None;
+        }
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let speed = self.done_bytes as f64 / elapsed;
+        let left = self.total_bytes.saturating_sub(self.done_bytes) as f64;
+        Some(Duration::from_secs_f64(left / speed))
+    }
+
+    pub fn describe(&self) -> String {
+        match self.remaining() {
+            Some(remaining) => format!("ETA {}", format_duration(remaining)),
+            None => "ETA unknown".to_string(),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted,
+    MessageFetched {
+        mailbox: &'a str,
+        uid: u32,
+        size: usize,
+    },
+    AttachmentSaved {
+        mailbox: &'a str,
+        uid: u64,
+        filename: &'a str,
+        path: &'a Path,
+        size: usize,
+        sha256: &'a str,
+    },
+    Error {
+        code: &'static str,
+        message: String,
+    },
+    RunFinished {
+        duration_ms: u128,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    timestamp: String,
+    run_id: &'a str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+static RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// Enables the event stream on stdout. Events are dropped until this is called.
+pub fn init(_format: EventFormat) {
+    let run_id = format!("{:x}", Utc::now().timestamp_millis());
+    *RUN_ID.lock().unwrap() = Some(run_id);
+}
+
+pub fn enabled() -> bool {
+    RUN_ID.lock().unwrap().is_some()
+}
+
+pub fn emit(event: Event<'_>) {
+    let run_id = RUN_ID.lock().unwrap();
+    let Some(run_id) = run_id.as_deref() else { return };
+
+    let envelope = Envelope {
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        run_id,
+        event: &event,
+    };
+
+    if let Ok(line) = serde_json::to_string(&envelope) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
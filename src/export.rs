@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampSecondArray, UInt32Array, UInt64Array};
+use parquet::arrow::ArrowWriter;
+
+use crate::MessageSource;
+
+struct MessageRow {
+    mailbox: String,
+    uid_validity: Option<u32>,
+    uid: u64,
+    message_id: Option<String>,
+    sender: Option<String>,
+    subject: Option<String>,
+    date: Option<i64>,
+    size: u64,
+    attachments: u32,
+}
+
+struct AttachmentRow {
+    mailbox: String,
+    uid: u64,
+    filename: String,
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Default)]
+struct Export {
+    messages: Vec<MessageRow>,
+    attachments: Vec<AttachmentRow>,
+}
+
+/// Rows collected during a run with `export_parquet` enabled; `None` while exporting is off.
+static EXPORT: Mutex<Option<Export>> = Mutex::new(None);
+
+fn with_export(f: impl FnOnce(&mut Export)) {
+    if let Some(export) = EXPORT.lock().unwrap().as_mut() {
+        f(export);
+    }
+}
+
+pub fn start() {
+    *EXPORT.lock().unwrap() = Some(Export::default());
+}
+
+pub fn message(source: &MessageSource, headers: &[mailparse::MailHeader<'_>], size: usize, attachments: u32) {
+    use mailparse::MailHeaderMap;
+
+    with_export(|export| export.messages.push(MessageRow {
+        mailbox: source.mailbox.clone(),
+        uid_validity: source.uid_validity,
+        uid: source.uid,
+        message_id: headers.get_first_value("Message-ID"),
+        sender: headers.get_first_value("From"),
+        subject: headers.get_first_value("Subject"),
+        date: headers.get_first_value("Date").and_then(|date| mailparse::dateparse(&date).ok()),
+        size: size as u64,
+        attachments,
+    }));
+}
+
+pub fn attachment(source: &MessageSource, filename: &str, path: &Path, size: usize, sha256: &str) {
+    with_export(|export| export.attachments.push(AttachmentRow {
+        mailbox: source.mailbox.clone(),
+        uid: source.uid,
+        filename: filename.to_string(),
+        path: path.to_string_lossy().into_owned(),
+        size: size as u64,
+        sha256: sha256.to_string(),
+    }));
+}
+
+fn write(path: &Path, columns: Vec<(&str, ArrayRef)>) -> Result<()> {
+    let batch = RecordBatch::try_from_iter(columns)?;
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes `<report>.messages.parquet` and `<report>.attachments.parquet` next to the run report.
+pub fn finish(report_path: &Path) -> Result<Option<(PathBuf, PathBuf)>> {
+    let Some(export) = EXPORT.lock().unwrap().take() else { return Ok(None) };
+    let messages_path = report_path.with_extension("messages.parquet");
+    let attachments_path = report_path.with_extension("attachments.parquet");
+
+    let messages = &export.messages;
+    write(&messages_path, vec![
+        ("mailbox", Arc::new(StringArray::from_iter_values(messages.iter().map(|m| &m.mailbox))) as ArrayRef),
+        ("uid_validity", Arc::new(UInt32Array::from_iter(messages.iter().map(|m| m.uid_validity)))),
+        ("uid", Arc::new(UInt64Array::from_iter_values(messages.iter().map(|m| m.uid)))),
+        ("message_id", Arc::new(StringArray::from_iter(messages.iter().map(|m| m.message_id.as_deref())))),
+        ("sender", Arc::new(StringArray::from_iter(messages.iter().map(|m| m.sender.as_deref())))),
+        ("subject", Arc::new(StringArray::from_iter(messages.iter().map(|m| m.subject.as_deref())))),
+        ("date", Arc::new(TimestampSecondArray::from_iter(messages.iter().map(|m| m.date)).with_timezone_utc())),
+        ("size", Arc::new(UInt64Array::from_iter_values(messages.iter().map(|m| m.size)))),
+        ("attachments", Arc::new(UInt32Array::from_iter_values(messages.iter().map(|m| m.attachments)))),
+    ])?;
+
+    let attachments = &export.attachments;
+    write(&attachments_path, vec![
+        ("mailbox", Arc::new(StringArray::from_iter_values(attachments.iter().map(|a| &a.mailbox))) as ArrayRef),
+        ("uid", Arc::new(UInt64Array::from_iter_values(attachments.iter().map(|a| a.uid)))),
+        ("filename", Arc::new(StringArray::from_iter_values(attachments.iter().map(|a| &a.filename)))),
+        ("path", Arc::new(StringArray::from_iter_values(attachments.iter().map(|a| &a.path)))),
+        ("size", Arc::new(UInt64Array::from_iter_values(attachments.iter().map(|a| a.size)))),
+        ("sha256", Arc::new(StringArray::from_iter_values(attachments.iter().map(|a| &a.sha256)))),
+    ])?;
+
+    Ok(Some((messages_path, attachments_path)))
+}
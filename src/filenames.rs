@@ -0,0 +1,222 @@
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normal form applied to saved filenames. macOS tends to produce NFD and
+/// everything else NFC, so without this the "same" name can exist twice side by side.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    None,
+    #[default]
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+pub fn normalize(filename: &str, form: Normalization, ascii: bool) -> String {
+    let normalized: String = match form {
+        Normalization::None => filename.to_string(),
+        Normalization::Nfc => filename.nfc().collect(),
+        Normalization::Nfd => filename.nfd().collect(),
+        Normalization::Nfkc => filename.nfkc().collect(),
+        Normalization::Nfkd => filename.nfkd().collect(),
+    };
+
+    if ascii {
+        deunicode::deunicode(&normalized)
+    } else {
+        normalized
+    }
+}
+
+/// Decodes RFC 2047 encoded-words such as `=?UTF-8?B?0YTQsNC50Ls=?=`. Strictly they are not
+/// allowed inside parameters, but many mailers put them in `name="..."` anyway.
+pub fn decode_encoded_words(value: &str) -> String {
+    if !value.contains("=?") {
+        return value.to_string();
+    }
+
+    // mailparse only decodes encoded-words while parsing a header, so wrap the value in one
+    match mailparse::parse_header(format!("X: {}", value).as_bytes()) {
+        Ok((header, _)) => header.get_value(),
+        Err(_) => value.to_string(),
+    }
+}
+
+/// Splits an RFC 2231 extended value, `charset'language'percent-encoded`, into its charset and data.
+fn split_extended(value: &str) -> (Option<&str>, &str) {
+    let mut fields = value.splitn(3, '\'');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(charset), Some(_language), Some(data)) => (Some(charset), data),
+        _ => (None, value),
+    }
+}
+
+fn decode_charset(charset: Option<&str>, bytes: &[u8]) -> String {
+    match charset.and_then(|label| charset::Charset::for_label(label.as_bytes())) {
+        Some(charset) => charset.decode_without_bom_handling(bytes).0.into_owned(),
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Looks up parameter `name` in a raw MIME parameter list, as BODYSTRUCTURE returns it, joining
+/// RFC 2231 continuations (`name*0*`, `name*1`, ...) and decoding charsets and encoded-words.
+pub fn mime_param<K: AsRef<str>, V: AsRef<str>>(params: &[(K, V)], name: &str) -> Option<String> {
+    let mut plain = None;
+    let mut segments: Vec<(u32, bool, &str)> = Vec::new();
+
+    for (key, value) in params {
+        let key = key.as_ref().to_ascii_lowercase();
+        let Some(rest) = key.strip_prefix(name) else { continue };
+        if rest.is_empty() {
+            plain = Some(value.as_ref());
+            continue;
+        }
+
+        // `name*` is a single extended value, `name*0`, `name*1*`, ... are continuations
+        let Some(rest) = rest.strip_prefix('*') else { continue };
+        let (index, encoded) = match rest.strip_suffix('*') {
+            Some(index) => (index, true),
+            None => (rest, rest.is_empty()),
+        };
+        let index = if index.is_empty() { Ok(0) } else { index.parse() };
+        if let Ok(index) = index {
+            segments.push((index, encoded, value.as_ref()));
+        }
+    }
+
+    if segments.is_empty() {
+        return plain.map(decode_encoded_words);
+    }
+
+    segments.sort_by_key(|(index, _, _)| *index);
+
+    // Only the first segment carries the charset. The raw bytes are joined before decoding
+    // because a multi-byte character may be split across two segments.
+    let mut charset = None;
+    let mut bytes = Vec::new();
+    for (position, &(_, encoded, value)) in segments.iter().enumerate() {
+        if !encoded {
+            bytes.extend(value.as_bytes());
+            continue;
+        }
+
+        let data = if position == 0 {
+            let (label, data) = split_extended(value);
+            charset = label;
+            data
+        } else {
+            value
+        };
+        match percent_decode(data) {
+            Some(decoded) => bytes.extend(decoded),
+            None => bytes.extend(data.as_bytes()),
+        }
+    }
+
+    Some(decode_charset(charset, &bytes))
+}
+
+/// What happens to characters that are not allowed in a filename on every platform:
+/// path separators, `:*?"<>|` and control characters.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sanitization {
+    /// Replace each with `_`.
+    #[default]
+    Replace,
+    /// Leave them out.
+    Strip,
+    /// Write them as `%XX`, like URLs; `%` itself is encoded too so names stay reversible.
+    PercentEncode,
+}
+
+const UNSAFE_CHARS: &str = "/\\:*?\"<>|";
+const MAX_NAME_BYTES: usize = 255;
+
+/// Device names Windows refuses as filenames, whatever the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Turns an attachment's name into a single filename that is valid on Windows, macOS and Linux,
+/// so names such as `../../etc/passwd` or `a:b.pdf` can neither escape the download directory nor
+/// fail to save. Trailing dots and spaces, which Windows drops, are removed as well, reserved device
+/// names get a `_` prefix and overlong names are shortened, keeping the extension.
+pub fn sanitize(filename: &str, policy: Sanitization) -> String {
+    let mut name = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        let unsafe_char = c.is_control() || UNSAFE_CHARS.contains(c) || (c == '%' && policy == Sanitization::PercentEncode);
+        if !unsafe_char {
+            name.push(c);
+            continue;
+        }
+
+        match policy {
+            Sanitization::Replace => name.push('_'),
+            Sanitization::Strip => {}
+            Sanitization::PercentEncode => {
+                for byte in c.to_string().bytes() {
+                    name.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+
+    let mut name = name.trim().trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() || name == "." || name == ".." {
+        name = "attachment".to_string();
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().to_ascii_lowercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        name.insert(0, '_');
+    }
+
+    truncate(&name)
+}
+
+/// Shortens a name to `MAX_NAME_BYTES`, cutting the stem rather than the extension.
+fn truncate(name: &str) -> String {
+    if name.len() <= MAX_NAME_BYTES {
+        return name.to_string();
+    }
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= 16 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = MAX_NAME_BYTES - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], extension)
+}
+
+/// Whether a relative path only has ordinary components, so joining it onto a directory stays inside it.
+pub fn stays_inside(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.components().next().is_some()
+        && path.components().all(|component| matches!(component, std::path::Component::Normal(_)))
+}
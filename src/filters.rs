@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use mailparse::MailHeaderMap;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+use crate::errors::ErrorCode;
+use crate::ImapConfig;
+
+/// Requires a header of the message to match a regular expression.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HeaderFilter {
+    pub name: String,
+    pub regex: String,
+}
+
+/// Patterns compiled so far, so each one is compiled once per run rather than once per email.
+static COMPILED: Mutex<Option<HashMap<String, Regex>>> = Mutex::new(None);
+
+fn compile(pattern: &str, option: &str) -> Result<Regex> {
+    let mut compiled = COMPILED.lock().unwrap();
+    let compiled = compiled.get_or_insert_with(HashMap::new);
+    if let Some(regex) = compiled.get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(pattern).with_context(|| format!("Invalid regular expression in {}", option)).context(ErrorCode::Config)?;
+    compiled.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// Whether the message passes `subject_contains`, `subject_regex` and `header_filters`, checked
+/// on the parsed headers since the server's SEARCH cannot express most of them. A message
+/// without the header a filter asks for does not match.
+pub fn headers_match(config: &ImapConfig, headers: &[mailparse::MailHeader<'_>]) -> Result<bool> {
+    let subject = headers.get_first_value("Subject").unwrap_or_default();
+
+    if let Some(text) = &config.subject_contains {
+        if !subject.to_lowercase().contains(&text.to_lowercase()) {
+            return Ok(false);
+        }
+    }
+    if let Some(pattern) = &config.subject_regex {
+        if !compile(pattern, "subject_regex")?.is_match(&subject) {
+            return Ok(false);
+        }
+    }
+    for filter in &config.header_filters {
+        let regex = compile(&filter.regex, &format!("header_filters ({})", filter.name))?;
+        if !headers.get_all_values(&filter.name).iter().any(|value| regex.is_match(value)) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use async_imap::types::NameAttribute;
+use futures::TryStreamExt;
+
+use crate::ImapSession;
+
+/// A mailbox as returned by LIST, with attributes flattened to their IMAP spelling (`\All`, `\Junk`, ...).
+pub struct Folder {
+    pub name: String,
+    pub attributes: Vec<String>,
+}
+
+impl Folder {
+    pub fn is_selectable(&self) -> bool {
+        !self.has_attribute("\\Noselect") && !self.has_attribute("\\NonExistent")
+    }
+
+    pub fn has_attribute(&self, attribute: &str) -> bool {
+        self.attributes.iter().any(|a| a.eq_ignore_ascii_case(attribute))
+    }
+
+    /// Entries of `exclude_folders` are either exact mailbox names or special-use attributes like `\Trash`.
+    pub fn is_excluded(&self, exclude_folders: &[String]) -> bool {
+        exclude_folders.iter().any(|pattern| {
+            if pattern.starts_with('\\') {
+                self.has_attribute(pattern)
+            } else {
+                self.name == *pattern
+            }
+        })
+    }
+}
+
+/// Matches a mailbox name against a pattern where `*` stands for any run of characters,
+/// including the hierarchy delimiter, and `?` for exactly one character.
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position of the last `*` and of the name character it currently swallows up to
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    n = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn attribute_name(attribute: &NameAttribute<'_>) -> String {
+    match attribute {
+        NameAttribute::NoInferiors => "\\Noinferiors".to_string(),
+        NameAttribute::NoSelect => "\\Noselect".to_string(),
+        NameAttribute::Marked => "\\Marked".to_string(),
+        NameAttribute::Unmarked => "\\Unmarked".to_string(),
+        NameAttribute::All => "\\All".to_string(),
+        NameAttribute::Archive => "\\Archive".to_string(),
+        NameAttribute::Drafts => "\\Drafts".to_string(),
+        NameAttribute::Flagged => "\\Flagged".to_string(),
+        NameAttribute::Junk => "\\Junk".to_string(),
+        NameAttribute::Sent => "\\Sent".to_string(),
+        NameAttribute::Trash => "\\Trash".to_string(),
+        NameAttribute::Extension(name) => name.to_string(),
+        _ => format!("{:?}", attribute),
+    }
+}
+
+pub async fn list(imap_session: &mut ImapSession) -> Result<Vec<Folder>> {
+    let folders_stream = imap_session.list(Some(""), Some("*")).await?;
+    let names: Vec<_> = folders_stream.try_collect().await?;
+
+    Ok(names.iter()
+        .map(|name| Folder {
+            name: name.name().to_string(),
+            attributes: name.attributes().iter().map(attribute_name).collect(),
+        })
+        .collect())
+}
@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::bodystructure::PartInfo;
+use crate::errors::ErrorCode;
+use crate::logging::{self, info, status, Status};
+use crate::resume::SeenMessages;
+use crate::shutdown;
+use crate::{
+    imap_quote, is_candidate_part, is_tnef_part, oauth, part_filename, process_message, tnef_attachments, AuthMethod, Direction,
+    EmailAttachment, FetchedMessage, ImapConfig, MessageSource,
+};
+
+const API_URL: &str = "https://gmail.googleapis.com/gmail/v1/users/me";
+
+/// Messages are reported as coming from this mailbox, as the API searches across all labels.
+const MAILBOX: &str = "Gmail API";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListResponse {
+    #[serde(default)]
+    messages: Vec<MessageRef>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessageRef {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    id: String,
+    thread_id: String,
+    size_estimate: usize,
+    payload: Part,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default)]
+    mime_type: String,
+    #[serde(default)]
+    filename: String,
+    #[serde(default)]
+    headers: Vec<Header>,
+    #[serde(default)]
+    body: Body,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Body {
+    attachment_id: Option<String>,
+    #[serde(default)]
+    size: u64,
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AttachmentResponse {
+    data: String,
+}
+
+impl Part {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// The same description BODYSTRUCTURE gives, so IMAP's part filters apply unchanged.
+    fn info(&self) -> PartInfo {
+        PartInfo {
+            section: Vec::new(),
+            content_type: self.mime_type.to_lowercase(),
+            filename: (!self.filename.is_empty()).then(|| self.filename.clone()),
+            content_id: self.header("Content-ID").map(|id| id.trim_matches(|c| c == '<' || c == '>').to_string()),
+            disposition: self.header("Content-Disposition")
+                .map(|value| value.split(';').next().unwrap_or_default().trim().to_lowercase()),
+            // The API reports decoded sizes
+            encoding: "binary".to_string(),
+            octets: self.body.size.min(u32::MAX as u64) as u32,
+        }
+    }
+
+    fn leaves(&self) -> Vec<&Part> {
+        if self.parts.is_empty() {
+            vec![self]
+        } else {
+            self.parts.iter().flat_map(Part::leaves).collect()
+        }
+    }
+}
+
+/// Gmail's base64url, with or without padding.
+fn decode(data: &str) -> Result<Vec<u8>> {
+    Ok(URL_SAFE_NO_PAD.decode(data.trim_end_matches('=')).context(ErrorCode::Parse)?)
+}
+
+/// The Gmail search equivalent of what `search_uids` asks the IMAP server for.
+fn search_query(config: &ImapConfig) -> String {
+    let mut senders = Vec::new();
+    for sender in config.senders() {
+        if config.direction != Direction::To {
+            senders.push(format!("from:{}", imap_quote(sender)));
+        }
+        if config.direction != Direction::From {
+            senders.push(format!("to:{}", imap_quote(sender)));
+        }
+    }
+
+    let mut terms = vec!["has:attachment".to_string()];
+    if !senders.is_empty() {
+        terms.push(format!("{{{}}}", senders.join(" ")));
+    }
+    if let Some(since) = config.since {
+        terms.push(format!("after:{}", since.format("%Y/%m/%d")));
+    }
+    if let Some(before) = config.before {
+        terms.push(format!("before:{}", before.format("%Y/%m/%d")));
+    }
+    if config.only_unseen {
+        terms.push("is:unread".to_string());
+    }
+    if config.only_flagged {
+        terms.push("is:starred".to_string());
+    }
+    if let Some(gmail_query) = &config.gmail_query {
+        terms.push(format!("({})", gmail_query));
+    }
+    terms.join(" ")
+}
+
+struct GmailClient<'a> {
+    http: reqwest::Client,
+    config: &'a ImapConfig,
+}
+
+impl GmailClient<'_> {
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T> {
+        // Fetched for every request, as a long run outlives an access token
+        let access_token = oauth::access_token(self.config).await?;
+        let response = self.http.get(format!("{}/{}", API_URL, path))
+            .bearer_auth(access_token)
+            .query(query)
+            .send().await.context(ErrorCode::Connect)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let code = if status.as_u16() == 401 || status.as_u16() == 403 { ErrorCode::Auth } else { ErrorCode::Imap };
+            return Err(anyhow!("Gmail API returned {} for {}: {}", status, path, body.trim()).context(code));
+        }
+        Ok(response.json().await.context(ErrorCode::Parse)?)
+    }
+
+    async fn list(&self, query: &str) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut page_token = None;
+
+        loop {
+            let mut params = vec![("q", query), ("maxResults", "500"), ("fields", "messages/id,nextPageToken")];
+            if let Some(token) = &page_token {
+                params.push(("pageToken", token.as_str()));
+            }
+            let page: ListResponse = self.get("messages", &params).await?;
+            ids.extend(page.messages.into_iter().map(|message| message.id));
+
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(ids),
+            }
+        }
+    }
+
+    async fn message(&self, id: &str) -> Result<Message> {
+        // A partial response leaves out everything but what the pipeline needs
+        self.get(&format!("messages/{}", id), &[
+            ("format", "full"),
+            ("fields", "id,threadId,sizeEstimate,payload"),
+        ]).await
+    }
+
+    async fn attachment(&self, message_id: &str, attachment_id: &str) -> Result<Vec<u8>> {
+        let response: AttachmentResponse = self.get(
+            &format!("messages/{}/attachments/{}", message_id, attachment_id),
+            &[("fields", "data")],
+        ).await?;
+        decode(&response.data)
+    }
+
+    /// Downloads the wanted parts of a message; small ones come inline with the message itself.
+    async fn attachments(&self, message: &Message) -> Result<Vec<EmailAttachment>> {
+        let config = self.config;
+        let mut attachments = Vec::new();
+
+        for part in message.payload.leaves() {
+            let info = part.info();
+            // A TNEF container is unpacked like on IMAP, and the size limit applies to the files inside
+            let tnef = is_tnef_part(&info.content_type, part_filename(&info).as_deref());
+            if !is_candidate_part(&info, config) || (!tnef && config.size_allowed(info.decoded_size()).is_err()) {
+                continue;
+            }
+            let Some(filename) = part_filename(&info).or_else(|| tnef.then(|| "winmail.dat".to_string())) else { continue };
+
+            let data = match (&part.body.data, &part.body.attachment_id) {
+                (Some(data), _) => decode(data)?,
+                (None, Some(attachment_id)) => self.attachment(&message.id, attachment_id).await?,
+                (None, None) => continue,
+            };
+            if tnef {
+                attachments.extend(tnef_attachments(&filename, data, config));
+            } else {
+                attachments.push(EmailAttachment { filename, data, container: None });
+            }
+        }
+
+        Ok(attachments)
+    }
+}
+
+/// The message's headers as raw lines, for the header parsing shared with IMAP.
+fn raw_headers(part: &Part) -> Vec<u8> {
+    let mut raw = String::new();
+    for header in &part.headers {
+        raw.push_str(&format!("{}: {}\r\n", header.name, header.value));
+    }
+    raw.push_str("\r\n");
+    raw.into_bytes()
+}
+
+/// Searches with the Gmail HTTP API instead of IMAP and passes every new message's wanted
+/// attachments to the same processing as IMAP messages.
+pub async fn sweep(config: &ImapConfig) -> Result<()> {
+    if config.auth != AuthMethod::Oauth2 {
+        return Err(anyhow!("protocol = \"gmail-api\" needs auth = \"oauth2\"").context(ErrorCode::Config));
+    }
+    tokio::fs::create_dir_all(&config.download_dir).await?;
+    std::fs::create_dir_all(&config.state_dir)?;
+
+    let client = GmailClient { http: reqwest::Client::new(), config };
+    let seen_path = SeenMessages::path_for(&config.state_dir, "gmail-api", &config.email);
+    let mut seen = if config.incremental { SeenMessages::load(&seen_path) } else { SeenMessages::default() };
+
+    let query = search_query(config);
+    let mut ids = client.list(&query).await?;
+    info!("Found {} emails matching {}", ids.len(), query);
+    ids.retain(|id| !seen.seen.contains(id));
+    // The API lists newest first
+    ids.reverse();
+    info!("Processing {} emails", ids.len());
+    logging::progress_start(ids.len() as u64);
+
+    for (i, id) in ids.iter().enumerate() {
+        if shutdown::requested() {
+            logging::progress_finish();
+            return Err(shutdown::interrupted());
+        }
+        status!(Status::Progress, "email {} ({}/{})", id, i + 1, ids.len());
+        let message = client.message(id).await?;
+        let attachments = client.attachments(&message).await?;
+
+        // Ids are 64-bit hex numbers, standing in for a UID in file names, reports and state
+        let source = MessageSource {
+            mailbox: MAILBOX.to_string(),
+            uid_validity: None,
+            uid: u64::from_str_radix(&message.id, 16).unwrap_or_default(),
+            thread_id: u64::from_str_radix(&message.thread_id, 16).ok(),
+        };
+        let fetched = FetchedMessage::Parts {
+            header: raw_headers(&message.payload),
+            attachments,
+            size: message.size_estimate,
+        };
+        process_message(source, fetched, config).await?;
+
+        seen.seen.insert(id.clone());
+        seen.save(&seen_path)?;
+        logging::progress_advance(1);
+    }
+
+    logging::progress_finish();
+    info!("-- All messages processed");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// One saved attachment, with enough information to find the message it came from again.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub mailbox: String,
+    pub uid_validity: Option<u32>,
+    pub uid: u64,
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub fn append(path: &Path, entry: &HistoryEntry) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Reads all entries, keeping only the most recent one per saved path.
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    let mut index: HashMap<PathBuf, usize> = HashMap::new();
+
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: HistoryEntry = serde_json::from_str(line)?;
+        match index.get(&entry.path) {
+            Some(&i) => entries[i] = entry,
+            None => {
+                index.insert(entry.path.clone(), entries.len());
+                entries.push(entry);
+            }
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+
+use crate::logging::{status, Status};
+use crate::manifest::ManifestEntry;
+use crate::{template, ImapConfig};
+
+/// A hook that has not finished by then is abandoned, so a stuck command or server cannot stall downloads.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Placeholders `on_download` knows.
+pub(crate) const PLACEHOLDERS: &[&str] = &["filename", "path", "mailbox", "uid", "sender", "subject", "size", "sha256"];
+
+/// Splits a command line into words like a POSIX shell does for quotes and backslashes, without
+/// expanding anything. Placeholders are filled in per word afterwards, so a filename can never
+/// turn into extra arguments or shell syntax.
+pub(crate) fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated ' in on_download")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(anyhow!("unterminated \" in on_download")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated \" in on_download")),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn vars(entry: &ManifestEntry<'_>) -> HashMap<&'static str, String> {
+    HashMap::from([
+        ("filename", entry.filename.to_string()),
+        ("path", entry.path.display().to_string()),
+        ("mailbox", entry.mailbox.to_string()),
+        ("uid", entry.uid.to_string()),
+        ("sender", entry.from.unwrap_or_default().to_string()),
+        ("subject", entry.subject.unwrap_or_default().to_string()),
+        ("size", entry.size.to_string()),
+        ("sha256", entry.sha256.to_string()),
+    ])
+}
+
+/// Runs `on_download` with the placeholders filled in and the values also in `GFD_*` variables.
+async fn run_command(command: &str, entry: &ManifestEntry<'_>) -> Result<()> {
+    let vars = vars(entry);
+    let words: Vec<String> = split_words(command)?.iter().map(|word| template::render(word, &vars)).collect();
+    let (program, args) = words.split_first().ok_or_else(|| anyhow!("on_download is empty"))?;
+
+    let mut child = tokio::process::Command::new(program);
+    child.args(args).stdin(std::process::Stdio::null()).kill_on_drop(true);
+    for (name, value) in &vars {
+        child.env(format!("GFD_{}", name.to_uppercase()), value);
+    }
+
+    let exit = tokio::time::timeout(HOOK_TIMEOUT, child.status()).await
+        .map_err(|_| anyhow!("still running after {} s", HOOK_TIMEOUT.as_secs()))?
+        .with_context(|| format!("cannot start {:?}", program))?;
+    if !exit.success() {
+        return Err(anyhow!("exited with {}", exit));
+    }
+    Ok(())
+}
+
+/// Posts the manifest line of the attachment as JSON.
+async fn post_webhook(url: &str, entry: &ManifestEntry<'_>) -> Result<()> {
+    let response = reqwest::Client::new().post(url)
+        .json(entry)
+        .timeout(HOOK_TIMEOUT)
+        .send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("answered {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Runs `on_download` and posts to `webhook_url` for a saved attachment. A failing hook is
+/// reported but never fails the download.
+pub async fn attachment_saved(config: &ImapConfig, entry: &ManifestEntry<'_>) {
+    if let Some(command) = &config.on_download {
+        if let Err(e) = run_command(command, entry).await {
+            status!(Status::Warning, "on_download for {:?} failed: {:#}", entry.path, e);
+        }
+    }
+    if let Some(url) = &config.webhook_url {
+        if let Err(e) = post_webhook(url, entry).await {
+            status!(Status::Warning, "webhook for {:?} failed: {:#}", entry.path, e);
+        }
+    }
+}
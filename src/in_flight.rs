@@ -0,0 +1,38 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::ImapConfig;
+
+/// The budget is counted in KiB, so even terabytes fit the semaphore's `u32` permits.
+const UNIT: u64 = 1024;
+
+/// Shared by every session of the process, with the limit it was created for.
+static BUDGET: Mutex<Option<(u64, Arc<Semaphore>)>> = Mutex::new(None);
+
+/// Part of `max_in_flight_bytes` held by one email from its fetch until its attachments are saved.
+pub type Reservation = Option<OwnedSemaphorePermit>;
+
+/// Waits until an email of `size` bytes fits into `max_in_flight_bytes` next to the emails
+/// already being fetched, parsed and saved. The raw message and its decoded attachments are
+/// in memory at the same time, so twice its size is reserved. An email larger than the whole
+/// budget waits for all others and then runs alone. Only admission is limited; nothing is
+/// streamed, so an admitted email is always buffered whole.
+pub async fn reserve(config: &ImapConfig, size: u64) -> Reservation {
+    let limit = config.max_in_flight_bytes.map(|limit| limit.0.div_ceil(UNIT)).filter(|&limit| limit > 0)?;
+
+    let semaphore = {
+        let mut budget = BUDGET.lock().unwrap();
+        match budget.as_ref() {
+            Some((current, semaphore)) if *current == limit => semaphore.clone(),
+            _ => {
+                let semaphore = Arc::new(Semaphore::new(limit.min(Semaphore::MAX_PERMITS as u64) as usize));
+                *budget = Some((limit, semaphore.clone()));
+                semaphore
+            }
+        }
+    };
+
+    let wanted = (size * 2).div_ceil(UNIT).clamp(1, limit.min(u32::MAX as u64)) as u32;
+    // The semaphore is never closed, so acquiring only fails if that changes
+    semaphore.acquire_many_owned(wanted).await.ok()
+}
@@ -0,0 +1,45 @@
+use std::path::Path;
+use anyhow::Result;
+use dialoguer::MultiSelect;
+
+use crate::logging::info;
+use crate::{connect_imap, credentials, folders, prompt_settings, save_config};
+
+/// Asks for the account settings, then lists the account's folders with their
+/// message counts so the source folders can be picked instead of typed.
+pub async fn run(path: &Path) -> Result<()> {
+    let mut config = prompt_settings()?;
+
+    let mut imap_session = connect_imap(&config).await?;
+    let folders = folders::list(&mut imap_session).await?;
+
+    let mut names = Vec::new();
+    let mut labels = Vec::new();
+    let mut defaults = Vec::new();
+
+    for folder in folders.iter().filter(|folder| !folder.has_attribute("\\Noselect")) {
+        let count = match imap_session.status(&folder.name, "(MESSAGES)").await {
+            Ok(mailbox) => mailbox.exists.to_string(),
+            Err(_) => "?".to_string(),
+        };
+
+        names.push(folder.name.clone());
+        labels.push(format!("{} ({} messages)", folder.name, count));
+        defaults.push(folder.has_attribute("\\All"));
+    }
+
+    imap_session.logout().await?;
+
+    let picked = MultiSelect::new()
+        .with_prompt("Select the folders to search (space to toggle, enter to confirm)")
+        .items(&labels)
+        .defaults(&defaults)
+        .interact()?;
+
+    config.folders = picked.into_iter().map(|i| names[i].clone()).collect();
+
+    credentials::move_to_keyring(&mut config);
+    save_config(path, &config)?;
+    info!("-- Saved {:?}", path);
+    Ok(())
+}
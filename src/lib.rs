@@ -0,0 +1,2482 @@
+//! Downloads email attachments over IMAP. The command line tool in `main.rs` is a thin layer
+//! over this library; other programs can embed it through [`Downloader`].
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use async_imap::{self, Session};
+use async_imap::imap_proto::types::SectionPath;
+use async_native_tls;
+use futures::stream::FuturesUnordered;
+use futures::{StreamExt, TryStreamExt};
+use tracing::Instrument;
+use mailparse;
+use mailparse::MailHeaderMap;
+use serde::{Serialize, Deserialize};
+use dialoguer::Input;
+use std::fs::File;
+use std::io::Write;
+use std::fs::read_to_string;
+use async_std::net::TcpStream;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+use chrono::{Datelike, Local, Months, NaiveDate};
+
+mod after_download;
+mod archives;
+mod body;
+mod bodystructure;
+mod categories;
+mod checksums;
+pub mod config_cmd;
+pub mod credentials;
+pub mod db;
+#[cfg(unix)]
+pub mod daemon;
+mod dedup;
+mod disk_guard;
+mod capabilities;
+mod downloader;
+pub mod diff;
+pub mod dry_run;
+pub mod estimate;
+pub mod errors;
+pub mod events;
+mod export;
+mod filenames;
+mod filters;
+mod folders;
+mod gmail_api;
+mod history;
+mod hooks;
+mod in_flight;
+pub mod init;
+pub mod list_folders;
+pub mod logging;
+mod manifest;
+pub mod oauth;
+pub mod paths;
+mod photos;
+pub mod picker;
+mod pop3;
+pub mod probe;
+mod proxy;
+mod report;
+mod resume;
+mod retry;
+mod secrets;
+pub mod shutdown;
+mod sink;
+mod sync;
+#[cfg(windows)]
+pub mod service;
+mod template;
+mod throttle;
+pub mod test_connection;
+mod threads;
+mod tnef;
+mod transfer;
+mod transport;
+mod uuencode;
+pub mod validate;
+pub mod verify;
+pub mod watch;
+mod xattrs;
+
+use bodystructure::PartInfo;
+pub use after_download::AfterDownload;
+pub use body::SaveBody;
+pub use categories::Category;
+pub use checksums::Checksums;
+use errors::ErrorCode;
+use estimate::{format_size, ByteSize, Eta};
+use events::Event;
+pub use filenames::{Normalization, Sanitization};
+pub use filters::HeaderFilter;
+use history::{HistoryEntry, HISTORY_FILE};
+use logging::{info, status, LogRotation, Status};
+use oauth::OAuth2Config;
+use resume::RunProgress;
+pub use sink::{SinkConfig, SinkType};
+pub use downloader::{AttachmentFilter, DownloadOptions, Downloader, SavedAttachment};
+use sync::FolderState;
+pub use transport::{Protocol, Security};
+use transport::{ImapStream, TraceStream};
+
+type ImapSession = Session<ImapStream>;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Ending of the hidden file a save is written to before it gets its final name.
+const PART_SUFFIX: &str = ".part";
+
+/// Where a fetched message lives on the server.
+#[derive(Clone, Debug)]
+struct MessageSource {
+    mailbox: String,
+    uid_validity: Option<u32>,
+    /// The IMAP UID, the Gmail API's 64-bit message id or a number derived from a POP3 UIDL.
+    uid: u64,
+    thread_id: Option<u64>,
+}
+
+#[derive(Debug)]
+struct EmailAttachment {
+    filename: String,
+    data: Vec<u8>,
+    /// Name of the TNEF container (`winmail.dat`) the file was unpacked from, the name that
+    /// BODYSTRUCTURE and so `--interactive` know it by.
+    container: Option<String>,
+}
+
+/// What to do when an attachment's target file already exists.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnExisting {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Keep the existing file and skip the attachment.
+    Skip,
+    /// Save under the first free `name (1).ext`, `name (2).ext`, ...
+    RenameWithSuffix,
+    /// Fail the run.
+    Error,
+    /// Skip if the existing file has the same size and SHA-256, otherwise save under a new name.
+    Verify,
+}
+
+/// How to log in to the server.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    #[default]
+    Password,
+    /// SASL XOAUTH2 with tokens from the `[oauth2]` client, obtained once in the browser.
+    Oauth2,
+}
+
+/// Which side of the conversation `sender` has to be on.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// Messages sent by `sender`.
+    From,
+    /// Messages sent to `sender`, searched in the Sent folder when there is one.
+    To,
+    #[default]
+    Both,
+}
+
+/// Order in which matching emails are processed. UIDs grow with arrival time, so sorting by them is enough.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Order {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+}
+
+impl Order {
+    fn sort(self, uids: HashSet<u32>) -> Vec<u32> {
+        let mut uids: Vec<u32> = uids.into_iter().collect();
+        uids.sort_unstable();
+        if self == Order::NewestFirst {
+            uids.reverse();
+        }
+        uids
+    }
+}
+
+/// Overrides applied to a single folder, so e.g. Receipts can go to its own directory
+/// with its own filters while All Mail keeps the global settings.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct FolderRule {
+    #[serde(default)]
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub download_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub categories: Option<Vec<Category>>,
+}
+
+/// Settings of a download run, as stored in config.toml.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ImapConfig {
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Password encrypted with a master passphrase, see `config encrypt-password`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_encrypted: Option<String>,
+    #[serde(default)]
+    pub sender: String,
+    /// More addresses searched in addition to `sender`.
+    #[serde(default)]
+    pub senders: Vec<String>,
+    /// Extra IMAP SEARCH keys, e.g. `SUBJECT "invoice"`; without any sender they select the emails alone.
+    #[serde(default)]
+    pub raw_query: Option<String>,
+    /// Native Gmail search, e.g. `has:attachment filename:pdf`, sent with X-GM-RAW.
+    #[serde(default)]
+    pub gmail_query: Option<String>,
+    /// Only emails whose subject contains this text, ignoring case.
+    #[serde(default)]
+    pub subject_contains: Option<String>,
+    #[serde(default)]
+    pub subject_regex: Option<String>,
+    /// Headers that must match, e.g. `{ name = "X-Mailer", regex = "^Scanner" }`.
+    #[serde(default)]
+    pub header_filters: Vec<HeaderFilter>,
+    pub download_dir: PathBuf,
+    pub server: String,
+    /// Also accepted as `backend`.
+    #[serde(default, alias = "backend")]
+    pub protocol: Protocol,
+    /// Defaults to 993 (POP3: 995) with `security = "tls"` and 143 (POP3: 110) otherwise.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    /// Tunnel the IMAP or POP3 connection through `socks5://[user:password@]host:port` or `http://...`.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub auth: AuthMethod,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth2: Option<OAuth2Config>,
+    /// Folders to search, as exact names or globs like `Receipts/*`; when empty the All Mail
+    /// (or Sent) folder is found automatically.
+    #[serde(default)]
+    pub folders: Vec<String>,
+    #[serde(default)]
+    pub folder_rules: BTreeMap<String, FolderRule>,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    #[serde(default = "categories::default_categories")]
+    pub categories: Vec<Category>,
+    /// MIME patterns such as `application/pdf`, `image/*` or `*`, saved in addition to `categories`.
+    #[serde(default)]
+    pub attachment_types: Vec<String>,
+    /// Filename globs such as `*.pdf`; when set, only attachments matching one of them are saved.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Filename globs such as `signature*`; matching attachments are skipped.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// How often a dropped connection is re-established before a folder is given up.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Seconds one email may take to fetch, parse and save before it is skipped; 0 disables the limit.
+    #[serde(default = "default_message_timeout")]
+    pub message_timeout: u64,
+    /// Emails fetched per minute at most, across all connections.
+    #[serde(default)]
+    pub max_fetches_per_minute: Option<u32>,
+    /// Bytes fetched per minute at most, across all connections, e.g. `"200MB"`.
+    #[serde(default)]
+    pub max_bytes_per_minute: Option<ByteSize>,
+    #[serde(default)]
+    pub max_attachments_per_message: Option<usize>,
+    /// Only save parts sent with `Content-Disposition: attachment`.
+    #[serde(default)]
+    pub attachments_only: bool,
+    /// With `attachments_only`, still save inline parts referenced by Content-ID from the HTML body.
+    #[serde(default)]
+    pub include_inline_cid: bool,
+    /// Attachments smaller than this, such as inline logos and tracking pixels, are skipped.
+    #[serde(default)]
+    pub min_size: Option<ByteSize>,
+    #[serde(default, alias = "max_attachment_size")]
+    pub max_size: Option<ByteSize>,
+    /// Stop the run once this much has been written.
+    #[serde(default)]
+    pub max_total_download: Option<ByteSize>,
+    /// Stop the run before a save would leave less than this free in `download_dir`.
+    #[serde(default)]
+    pub min_free_space: Option<ByteSize>,
+    #[serde(default)]
+    pub on_existing: OnExisting,
+    /// Write a `.sha256` file next to every attachment or collect them in `SHA256SUMS`.
+    #[serde(default)]
+    pub checksums: Checksums,
+    /// Upload attachments to S3 or WebDAV instead of writing them to `download_dir`.
+    #[serde(default)]
+    pub sink: SinkConfig,
+    #[serde(default = "default_exclude_folders")]
+    pub exclude_folders: Vec<String>,
+    #[serde(default)]
+    pub order: Order,
+    /// Only search emails received on or after this date.
+    #[serde(default)]
+    pub since: Option<NaiveDate>,
+    /// Only search emails received before this date.
+    #[serde(default)]
+    pub before: Option<NaiveDate>,
+    #[serde(default)]
+    pub monthly_search: bool,
+    /// Only look at emails that are new or changed since the last completed run of each folder.
+    #[serde(default = "default_incremental")]
+    pub incremental: bool,
+    #[serde(default)]
+    pub prefilter: bool,
+    #[serde(default)]
+    pub fetch_parts: bool,
+    /// Save attachments whose transfer encoding is unknown or broken as they are, instead of skipping them.
+    #[serde(default)]
+    pub assume_binary: bool,
+    /// Ask the server for `COMPRESS DEFLATE` (RFC 4978) after logging in, if it offers it.
+    #[serde(default)]
+    pub compress: bool,
+    /// Also save every processed email as a `.eml` file into `eml_dir`.
+    #[serde(default)]
+    pub save_eml: bool,
+    /// Save only the `.eml` files, without extracting attachments.
+    #[serde(default)]
+    pub eml_only: bool,
+    /// Relative to `download_dir` unless absolute.
+    #[serde(default = "default_eml_dir")]
+    pub eml_dir: PathBuf,
+    /// Also save the text and/or HTML body of every email as `{uid}-body.txt` / `{uid}-body.html`.
+    #[serde(default)]
+    pub save_body: SaveBody,
+    /// IMAP sessions fetching emails in parallel.
+    #[serde(default = "default_connections")]
+    pub connections: usize,
+    /// Memory the emails being fetched, parsed and saved may take together, e.g. `"256MB"`; `0` disables the limit.
+    #[serde(default = "default_max_in_flight_bytes")]
+    pub max_in_flight_bytes: Option<ByteSize>,
+    #[serde(default)]
+    pub filename_template: Option<String>,
+    /// Relative path of each saved file, e.g. `{sender}/{year}/{month}/{filename}`.
+    #[serde(default)]
+    pub path_template: Option<String>,
+    /// Also save the files inside zip attachments, next to the archive.
+    #[serde(default)]
+    pub extract_archives: bool,
+    /// With `extract_archives`, also extract `.tar.gz` attachments.
+    #[serde(default)]
+    pub extract_tar_gz: bool,
+    /// Remove an archive once its files are extracted.
+    #[serde(default)]
+    pub delete_extracted_archives: bool,
+    /// How characters that are unsafe in filenames are handled.
+    #[serde(default)]
+    pub filename_sanitization: Sanitization,
+    #[serde(default)]
+    pub filename_normalization: Normalization,
+    #[serde(default)]
+    pub ascii_filenames: bool,
+    /// Skip attachments whose content was already saved, from this or an earlier email.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Process an email only once per run when it shows up in several folders, e.g. in All Mail and under a label.
+    #[serde(default = "default_dedup_messages")]
+    pub dedup_messages: bool,
+    #[serde(default)]
+    pub write_xattrs: bool,
+    /// Set each saved file's modification (and creation) time to the email's Date header.
+    #[serde(default)]
+    pub preserve_email_date: bool,
+    /// Rename JPEG and HEIC photos after their EXIF date and camera and sort them into `YYYY/MM/DD`.
+    #[serde(default)]
+    pub organize_photos: bool,
+    #[serde(default)]
+    pub export_parquet: bool,
+    /// Flag set on every processed email, e.g. `\\Seen` or `$AttachmentsDownloaded`.
+    #[serde(default)]
+    pub mark_flag: Option<String>,
+    /// Gmail label applied to every processed email.
+    #[serde(default)]
+    pub mark_label: Option<String>,
+    /// Leave out emails that already carry `mark_flag` or `mark_label` when searching.
+    #[serde(default)]
+    pub skip_marked: bool,
+    /// Only search emails without the `\Seen` flag.
+    #[serde(default)]
+    pub only_unseen: bool,
+    /// Only search emails with the `\Flagged` flag (starred in Gmail).
+    #[serde(default)]
+    pub only_flagged: bool,
+    /// Leave out emails marked `\Deleted` but not yet expunged.
+    #[serde(default)]
+    pub skip_deleted: bool,
+    /// Fetch with `BODY.PEEK[]` instead of `RFC822`, so downloading does not mark emails as read.
+    #[serde(default)]
+    pub peek: bool,
+    /// `"none"`, `"mark-seen"`, `"move:<folder>"` or `"delete"`, applied to every processed email.
+    #[serde(default)]
+    pub after_download: AfterDownload,
+    /// JSON Lines file getting one line per saved attachment.
+    #[serde(default)]
+    pub manifest: Option<PathBuf>,
+    /// Command run after each saved attachment, with placeholders such as `{filename}` and `{path}`.
+    #[serde(default)]
+    pub on_download: Option<String>,
+    /// URL receiving a POST of the manifest line of each saved attachment.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub group_by_thread: bool,
+    #[serde(default)]
+    pub include_spam: bool,
+    #[serde(default)]
+    pub include_trash: bool,
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+    /// SQLite database of processed emails and saved attachments; `state.sqlite` in `state_dir` by default.
+    #[serde(default)]
+    pub db_path: Option<PathBuf>,
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    #[serde(default = "default_log_max_size")]
+    pub log_max_size: u64,
+    #[serde(default = "default_log_keep")]
+    pub log_keep: usize,
+    /// Further accounts, each a `name` plus any settings that differ from the ones above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<toml::Table>,
+    /// Name of the `[[accounts]]` entry this configuration was built from.
+    #[serde(skip)]
+    pub account: Option<String>,
+    /// Log the raw IMAP exchange, set with `--imap-trace`.
+    #[serde(skip)]
+    pub imap_trace: bool,
+    /// Attachments picked with `--interactive`; when set, nothing else is downloaded.
+    #[serde(skip)]
+    pub selection: Option<Arc<picker::Selection>>,
+    /// `--yes-really-delete` was given, which `after_download = "delete"` needs.
+    #[serde(skip)]
+    pub confirm_delete: bool,
+    /// Where this configuration was loaded from; files such as the OAuth2 token cache live next to it.
+    #[serde(skip)]
+    pub config_path: PathBuf,
+}
+
+impl Default for ImapConfig {
+    fn default() -> Self {
+        ImapConfig {
+            email: String::new(),
+            password: None,
+            password_encrypted: None,
+            sender: String::new(),
+            senders: Vec::new(),
+            raw_query: None,
+            gmail_query: None,
+            subject_contains: None,
+            subject_regex: None,
+            header_filters: Vec::new(),
+            server: "imap.gmail.com".to_string(),
+            protocol: Protocol::default(),
+            port: None,
+            security: Security::default(),
+            proxy: None,
+            download_dir: PathBuf::from("./downloaded_images"),
+            auth: AuthMethod::default(),
+            oauth2: None,
+            folders: Vec::new(),
+            folder_rules: BTreeMap::new(),
+            direction: Direction::default(),
+            poll_interval: default_poll_interval(),
+            categories: categories::default_categories(),
+            attachment_types: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_retries: default_max_retries(),
+            message_timeout: default_message_timeout(),
+            max_fetches_per_minute: None,
+            max_bytes_per_minute: None,
+            max_attachments_per_message: None,
+            attachments_only: false,
+            include_inline_cid: false,
+            min_size: None,
+            max_size: None,
+            max_total_download: None,
+            min_free_space: None,
+            on_existing: OnExisting::default(),
+            checksums: Checksums::default(),
+            sink: SinkConfig::default(),
+            exclude_folders: default_exclude_folders(),
+            order: Order::default(),
+            since: None,
+            before: None,
+            monthly_search: false,
+            incremental: default_incremental(),
+            prefilter: false,
+            fetch_parts: false,
+            assume_binary: false,
+            compress: false,
+            save_eml: false,
+            eml_only: false,
+            eml_dir: default_eml_dir(),
+            save_body: SaveBody::default(),
+            connections: default_connections(),
+            max_in_flight_bytes: default_max_in_flight_bytes(),
+            filename_template: None,
+            path_template: None,
+            extract_archives: false,
+            extract_tar_gz: false,
+            delete_extracted_archives: false,
+            filename_sanitization: Sanitization::default(),
+            filename_normalization: Normalization::default(),
+            ascii_filenames: false,
+            dedup: false,
+            dedup_messages: default_dedup_messages(),
+            write_xattrs: false,
+            preserve_email_date: false,
+            organize_photos: false,
+            export_parquet: false,
+            mark_flag: None,
+            mark_label: None,
+            skip_marked: false,
+            only_unseen: false,
+            only_flagged: false,
+            skip_deleted: false,
+            peek: false,
+            after_download: AfterDownload::default(),
+            manifest: None,
+            on_download: None,
+            webhook_url: None,
+            group_by_thread: false,
+            include_spam: false,
+            include_trash: false,
+            state_dir: default_state_dir(),
+            db_path: None,
+            log_file: None,
+            log_rotation: LogRotation::default(),
+            log_max_size: default_log_max_size(),
+            log_keep: default_log_keep(),
+            accounts: Vec::new(),
+            account: None,
+            imap_trace: false,
+            selection: None,
+            confirm_delete: false,
+            config_path: PathBuf::from(CONFIG_FILE),
+        }
+    }
+}
+
+impl ImapConfig {
+    pub fn db_path(&self) -> PathBuf {
+        self.db_path.clone().unwrap_or_else(|| self.state_dir.join(db::DB_FILE))
+    }
+
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(self.security.default_port(self.protocol))
+    }
+
+    /// With `attachments_only`, keeps parts sent as attachments and drops inline ones such as
+    /// signature logos; inline parts referenced by Content-ID need `include_inline_cid`.
+    fn disposition_allowed(&self, disposition: Option<&str>, has_content_id: bool) -> bool {
+        if !self.attachments_only {
+            return true;
+        }
+
+        match disposition {
+            Some("attachment") => true,
+            _ if has_content_id => self.include_inline_cid,
+            // Old mailers mark attachments only by naming them
+            None => true,
+            Some(_) => false,
+        }
+    }
+
+    /// Checks an attachment size against `min_size` and `max_size`, returning why it is rejected.
+    fn size_allowed(&self, size: u64) -> std::result::Result<(), &'static str> {
+        match (self.min_size, self.max_size) {
+            (Some(ByteSize(min_size)), _) if size < min_size => Err("is below min_size"),
+            (_, Some(ByteSize(max_size))) if size > max_size => Err("exceeds max_size"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a decoded attachment name against `include` and `exclude`, ignoring case.
+    fn filename_allowed(&self, filename: &str) -> std::result::Result<(), &'static str> {
+        let filename = filename.to_lowercase();
+        let matches = |pattern: &String| folders::matches_glob(&pattern.to_lowercase(), &filename);
+        if !self.include.is_empty() && !self.include.iter().any(matches) {
+            Err("matches no include pattern")
+        } else if self.exclude.iter().any(matches) {
+            Err("matches an exclude pattern")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Names of the `[[accounts]]` entries, in file order.
+    pub fn account_names(&self) -> Vec<&str> {
+        self.accounts.iter()
+            .filter_map(|account| account.get("name")?.as_str())
+            .collect()
+    }
+
+    /// Settings of one `[[accounts]]` entry: its own keys on top of the top-level ones. Unless the
+    /// entry sets `state_dir`, its state is kept in a subdirectory so accounts never share resume files.
+    pub fn for_account(&self, name: &str) -> Result<ImapConfig> {
+        let account = self.accounts.iter()
+            .find(|account| account.get("name").and_then(toml::Value::as_str) == Some(name))
+            .ok_or_else(|| anyhow::anyhow!("No account named \"{}\" in {:?}", name, self.config_path))
+            .context(ErrorCode::Config)?;
+
+        let mut merged = toml::Table::try_from(self)?;
+        merged.remove("accounts");
+        merged.extend(account.iter()
+            .filter(|(key, _)| *key != "name")
+            .map(|(key, value)| (key.clone(), value.clone())));
+
+        let mut config: ImapConfig = toml::Value::Table(merged).try_into()
+            .with_context(|| format!("Invalid settings for account \"{}\"", name))
+            .context(ErrorCode::Config)?;
+        config.config_path = self.config_path.clone();
+        config.account = Some(name.to_string());
+        if !account.contains_key("state_dir") {
+            config.state_dir = self.state_dir.join(resume::mailbox_slug(name));
+        }
+        Ok(config)
+    }
+
+    /// `sender` and `senders` combined, skipping empty entries.
+    fn senders(&self) -> Vec<&str> {
+        std::iter::once(&self.sender)
+            .chain(&self.senders)
+            .map(String::as_str)
+            .filter(|sender| !sender.is_empty())
+            .collect()
+    }
+
+    /// The configuration to use for one mailbox, with its `folder_rules` entry applied.
+    fn for_folder(&self, mailbox_name: &str) -> ImapConfig {
+        let mut config = self.clone();
+
+        if let Some(rule) = self.folder_rules.get(mailbox_name) {
+            if let Some(sender) = &rule.sender {
+                config.sender = sender.clone();
+                config.senders.clear();
+            }
+            if let Some(download_dir) = &rule.download_dir {
+                config.download_dir = download_dir.clone();
+            }
+            if let Some(categories) = &rule.categories {
+                config.categories = categories.clone();
+            }
+        }
+
+        config
+    }
+}
+
+fn default_eml_dir() -> PathBuf {
+    PathBuf::from("eml")
+}
+
+fn default_connections() -> usize {
+    1
+}
+
+fn default_max_in_flight_bytes() -> Option<ByteSize> {
+    Some(ByteSize(256_000_000))
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_message_timeout() -> u64 {
+    600
+}
+
+fn default_incremental() -> bool {
+    true
+}
+
+fn default_dedup_messages() -> bool {
+    true
+}
+
+fn default_poll_interval() -> u64 {
+    300
+}
+
+fn default_exclude_folders() -> Vec<String> {
+    vec!["\\Junk".to_string(), "\\Trash".to_string()]
+}
+
+fn default_state_dir() -> PathBuf {
+    paths::state_dir()
+}
+
+fn default_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_log_keep() -> usize {
+    5
+}
+
+fn prompt_settings() -> Result<ImapConfig> {
+    let email: String = Input::new()
+        .with_prompt("Enter your email")
+        .interact_text()?;
+
+    let password: String = Input::new()
+        .with_prompt("Enter your password")
+        .interact_text()?;
+
+    let sender: String = Input::new()
+        .with_prompt("Enter the sender email")
+        .interact_text()?;
+
+    let server: String = Input::new()
+        .with_prompt("Enter the IMAP server (e.g., imap.gmail.com)")
+        .default("imap.gmail.com".to_string())
+        .interact_text()?;
+
+    let download_dir: String = Input::new()
+        .with_prompt("Enter the download directory")
+        .default("./downloaded_images".to_string())
+        .interact_text()?;
+
+    Ok(ImapConfig {
+        email,
+        password: Some(password),
+        sender,
+        server,
+        download_dir: PathBuf::from(download_dir),
+        ..ImapConfig::default()
+    })
+}
+
+fn save_config(path: &Path, config: &ImapConfig) -> Result<()> {
+    let toml_string = toml::to_string(config)?;
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    file.write_all(toml_string.as_bytes())?;
+    Ok(())
+}
+
+async fn open_tcp(config: &ImapConfig) -> Result<TcpStream> {
+    let Some(proxy) = &config.proxy else {
+        return Ok(TcpStream::connect((config.server.as_str(), config.port())).await.context(ErrorCode::Connect)?);
+    };
+
+    let proxy = proxy::Proxy::parse(proxy).context(ErrorCode::Config)?;
+    proxy.connect(&config.server, config.port()).await.context(ErrorCode::Connect)
+}
+
+/// Encrypts the connection as `security` asks, upgrading it with STARTTLS first if needed.
+async fn tls_handshake(config: &ImapConfig, tcp_stream: TcpStream) -> Result<ImapStream> {
+    let tcp_stream = match config.security {
+        Security::Tls => tcp_stream,
+        Security::Starttls => {
+            let mut client = async_imap::Client::new(tcp_stream);
+            client.run_command_and_check_ok("STARTTLS", None).await.context(ErrorCode::Tls)?;
+            client.into_inner()
+        }
+        Security::None if transport::is_loopback(&config.server) => return Ok(ImapStream::Plain(tcp_stream)),
+        Security::None => {
+            return Err(anyhow::anyhow!("security = \"none\" is only allowed for servers on localhost, not {}", config.server)
+                .context(ErrorCode::Config));
+        }
+    };
+
+    let tls = async_native_tls::TlsConnector::new();
+    Ok(ImapStream::Tls(tls.connect(config.server.as_str(), tcp_stream).await.context(ErrorCode::Tls)?))
+}
+
+async fn login(config: &ImapConfig, tls_stream: ImapStream) -> Result<ImapSession> {
+    let tls_stream = if config.imap_trace {
+        ImapStream::Traced(Box::new(TraceStream::new(tls_stream)))
+    } else {
+        tls_stream
+    };
+    let client = async_imap::Client::new(tls_stream);
+
+    let session = match config.auth {
+        AuthMethod::Password => {
+            let password = config.password.as_deref().unwrap_or_default();
+            client.login(&config.email, password).await.map_err(|e| anyhow::Error::new(e.0))
+        }
+        AuthMethod::Oauth2 => {
+            let authenticator = oauth::BearerAuth::new(config).await?;
+            let rejection = Arc::clone(&authenticator.rejection);
+            client.authenticate(authenticator.mechanism.name(), authenticator).await
+                .map_err(|e| match rejection.lock().unwrap().take() {
+                    Some(reason) => anyhow::Error::new(e.0).context(format!("access token rejected: {}", reason)),
+                    None => anyhow::Error::new(e.0),
+                })
+        }
+    };
+
+    Ok(session.context(ErrorCode::Auth)?)
+}
+
+async fn connect_imap(config: &ImapConfig) -> Result<ImapSession> {
+    let tcp_stream = open_tcp(config).await?;
+    let tls_stream = tls_handshake(config, tcp_stream).await?;
+    info!("-- Connected to {}:{}", config.server, config.port());
+
+    let imap_session = login(config, tls_stream).await?;
+    info!("-- Logged in as {}", config.email);
+
+    // A trace of deflated bytes would be unreadable
+    if config.compress && !config.imap_trace {
+        return compress(imap_session).await;
+    }
+    Ok(imap_session)
+}
+
+/// Switches the session to `COMPRESS DEFLATE` when the server advertises it, and keeps it as it is otherwise.
+async fn compress(mut imap_session: ImapSession) -> Result<ImapSession> {
+    if !imap_session.capabilities().await?.has_str("COMPRESS=DEFLATE") {
+        info!("-- The server does not offer COMPRESS=DEFLATE, continuing uncompressed");
+        return Ok(imap_session);
+    }
+
+    let imap_session = imap_session
+        .compress(|stream| ImapStream::Compressed(Box::new(stream)))
+        .await
+        .context(ErrorCode::Imap)?;
+    info!("-- Compression enabled");
+    Ok(imap_session)
+}
+
+fn uid_set(uids: &[u32]) -> String {
+    uids.iter()
+        .map(|uid| uid.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Fetches RFC822.SIZE of the given messages. Attachments are base64 on the wire,
+/// so the sum overestimates what lands on disk, which is what a preflight check wants.
+async fn fetch_sizes(imap_session: &mut ImapSession, uids: &[u32]) -> Result<HashMap<u32, u64>> {
+    let mut sizes = HashMap::new();
+
+    for chunk in uids.chunks(500) {
+        let mut messages_stream = imap_session.uid_fetch(uid_set(chunk), "RFC822.SIZE").await?;
+
+        while let Some(message) = messages_stream.try_next().await? {
+            if let Some(uid) = message.uid {
+                sizes.insert(uid, message.size.unwrap_or(0) as u64);
+            }
+        }
+    }
+
+    Ok(sizes)
+}
+
+fn check_free_space(dir: &Path, required: u64) -> Result<()> {
+    let available = fs2::available_space(dir)?;
+
+    if required > available {
+        return Err(anyhow::anyhow!(
+            "Not enough free space in {:?}: about {} needed but only {} available",
+            dir, format_size(required), format_size(available)
+        ).context(ErrorCode::DiskFull));
+    }
+
+    Ok(())
+}
+
+async fn is_same_file(path: &Path, data: &[u8]) -> Result<bool> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if metadata.len() != data.len() as u64 {
+        return Ok(false);
+    }
+
+    let existing = tokio::fs::read(path).await?;
+    Ok(Sha256::digest(&existing) == Sha256::digest(data))
+}
+
+/// Hidden file next to `path` that a save is written to before it is moved into place.
+fn part_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}{}", name, PART_SUFFIX))
+}
+
+fn is_part_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(PART_SUFFIX))
+}
+
+/// Writes `data` to a `.part` file next to `path`, flushes it to disk and only then moves it to `path`,
+/// so a crash or a full disk never leaves a truncated file under the final name. Without `replace` an
+/// existing file, or one being saved in parallel, is left alone and `false` returned.
+async fn write_atomic(path: &Path, data: &[u8], replace: bool) -> Result<bool> {
+    use tokio::io::AsyncWriteExt;
+
+    let part_path = part_path(path);
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true);
+    if replace {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = match options.open(&part_path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let written = async {
+        file.write_all(data).await?;
+        file.sync_all().await
+    }.await;
+    drop(file);
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        return Err(e.into());
+    }
+
+    if replace {
+        tokio::fs::rename(&part_path, path).await?;
+        return Ok(true);
+    }
+
+    // A hard link claims the name only if it is free, which a rename cannot do
+    let claimed = match tokio::fs::hard_link(&part_path, path).await {
+        Ok(()) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => false,
+        // File systems without hard links, e.g. FAT
+        Err(_) if !tokio::fs::try_exists(path).await? => {
+            tokio::fs::rename(&part_path, path).await?;
+            return Ok(true);
+        }
+        Err(_) => false,
+    };
+    tokio::fs::remove_file(&part_path).await?;
+    Ok(claimed)
+}
+
+/// Creates `path` and writes `data` to it, returning `false` without touching anything if it already exists.
+async fn write_new(path: &Path, data: &[u8]) -> Result<bool> {
+    write_atomic(path, data, false).await
+}
+
+/// Deletes `.part` files left in `dir` by a run that crashed or was killed while saving.
+fn remove_stale_parts(dir: &Path) -> Result<usize> {
+    let mut removed = 0;
+    let Ok(entries) = std::fs::read_dir(dir) else { return Ok(0) };
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            removed += remove_stale_parts(&path)?;
+        } else if is_part_file(&path) {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Saves to `path`, or if taken to `name (1).ext`, `name (2).ext`, ... like browsers do for downloads.
+/// The name is claimed with `create_new`, so attachments saved in parallel never end up in the same file.
+async fn write_unique(path: &Path, data: &[u8]) -> Result<PathBuf> {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    if write_new(path, data).await? {
+        return Ok(path.to_path_buf());
+    }
+
+    for n in 1.. {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+        if write_new(&candidate, data).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+async fn save_attachment(data: &[u8], filename: &str, dir: &Path, on_existing: OnExisting) -> Result<Option<PathBuf>> {
+    // Names are sanitized long before, but a template could still produce an absolute path or `..`
+    if !filenames::stays_inside(filename) {
+        return Err(anyhow::anyhow!("{:?} would be saved outside {:?}", filename, dir).context(ErrorCode::Io));
+    }
+    let path = dir.join(filename);
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let path = match on_existing {
+        OnExisting::Overwrite => {
+            write_atomic(&path, data, true).await?;
+            path
+        }
+        OnExisting::RenameWithSuffix => write_unique(&path, data).await?,
+        _ if write_new(&path, data).await? => path,
+        OnExisting::Skip => {
+            status!(Status::Skipped, "{:?} (file exists)", path);
+            report::attachment_skipped();
+            return Ok(None);
+        }
+        OnExisting::Error => {
+            return Err(anyhow::anyhow!("{:?} already exists", path).context(ErrorCode::Io));
+        }
+        OnExisting::Verify if is_same_file(&path, data).await? => {
+            status!(Status::Skipped, "{:?} (identical file exists)", path);
+            report::attachment_skipped();
+            return Ok(None);
+        }
+        OnExisting::Verify => write_unique(&path, data).await?,
+    };
+
+    status!(Status::Saved, "{:?}", path);
+    tracing::debug!(path = ?path, "attachment written");
+    Ok(Some(path))
+}
+
+/// Uploads an attachment to the configured `[sink]` instead of writing it below `download_dir`.
+/// The returned location is a URL, recorded wherever a saved file's path would be.
+async fn upload_attachment(
+    data: &[u8],
+    filename: &str,
+    dir: &Path,
+    config: &ImapConfig,
+    path_vars: &HashMap<&'static str, String>,
+) -> Result<Option<PathBuf>> {
+    if !filenames::stays_inside(filename) {
+        return Err(anyhow::anyhow!("{:?} would be saved outside {:?}", filename, dir).context(ErrorCode::Io));
+    }
+    let relative = dir.strip_prefix(&config.download_dir).unwrap_or(Path::new("")).join(filename);
+    let key = config.sink.key(&relative.to_string_lossy(), path_vars);
+
+    let location = sink::upload(&config.sink, &key, data).await?;
+    status!(Status::Saved, "{}", location);
+    tracing::debug!(location = %location, "attachment uploaded");
+    Ok(Some(PathBuf::from(location)))
+}
+
+/// Sets the modification time, and where the platform keeps one the creation time, of a saved file.
+fn set_file_date(path: &Path, date: chrono::DateTime<chrono::Utc>) -> Result<()> {
+    let time = std::time::SystemTime::from(date);
+    let times = std::fs::FileTimes::new().set_accessed(time).set_modified(time);
+    #[cfg(windows)]
+    let times = std::os::windows::fs::FileTimesExt::set_created(times, time);
+    #[cfg(target_os = "macos")]
+    let times = std::os::macos::fs::FileTimesExt::set_created(times, time);
+
+    File::options().write(true).open(path)?.set_times(times)?;
+    Ok(())
+}
+
+/// A saved attachment or a file taken out of one, as recorded in the report, manifest, db and history.
+struct ExtractedFile {
+    filename: String,
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+/// Saves the files of a zip attachment, or with `extract_tar_gz` a .tar.gz one, into `dir`, named
+/// and handled like attachments. Returns `None` for attachments that are no archive and archives
+/// that cannot be read, which are kept as they are.
+async fn extract_archive(data: &[u8], archive_path: &Path, dir: &Path, config: &ImapConfig) -> Result<Option<Vec<ExtractedFile>>> {
+    let format = match archives::detect(data) {
+        Some(archives::Format::Zip) => archives::Format::Zip,
+        Some(archives::Format::TarGz) if config.extract_tar_gz => archives::Format::TarGz,
+        _ => return Ok(None),
+    };
+
+    let max_size = config.max_size.map(|size| size.0);
+    let data = data.to_vec();
+    let entries = match tokio::task::spawn_blocking(move || archives::entries(&data, format, max_size)).await? {
+        Ok(entries) => entries,
+        Err(e) => {
+            status!(Status::Warning, "cannot extract {:?}: {:#}", archive_path, e);
+            return Ok(None);
+        }
+    };
+
+    info!("-- Extracting {} files from {:?}", entries.len(), archive_path);
+    let mut extracted = Vec::new();
+    for entry in entries {
+        // Folders inside the archive are kept, each name sanitized like an attachment's
+        let filename = entry.name.split('/')
+            .map(|part| filenames::sanitize(part, config.filename_sanitization))
+            .collect::<Vec<_>>()
+            .join("/");
+        let filename = filenames::normalize(&filename, config.filename_normalization, config.ascii_filenames);
+        if let Some(path) = save_attachment(&entry.data, &filename, dir, config.on_existing).await? {
+            extracted.push(ExtractedFile {
+                filename,
+                path,
+                size: entry.data.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(&entry.data)),
+            });
+        }
+    }
+
+    if config.delete_extracted_archives {
+        tokio::fs::remove_file(archive_path).await?;
+    }
+    Ok(Some(extracted))
+}
+
+fn get_content_type(part: &mailparse::ParsedMail<'_>) -> Option<String> {
+    part.headers.get_first_header("Content-Type")
+        .map(|h| h.get_value().to_lowercase())
+}
+
+fn get_filename(part: &mailparse::ParsedMail<'_>) -> Option<String> {
+    // mailparse joins RFC 2231 continuations itself; mime_param still catches anything it
+    // left encoded and decodes the RFC 2047 encoded-words many mailers use instead
+    let content_type: Vec<_> = part.ctype.params.iter().collect();
+    let disposition = part.get_content_disposition();
+    let disposition: Vec<_> = disposition.params.iter().collect();
+
+    filenames::mime_param(&content_type, "name")
+        .or_else(|| filenames::mime_param(&disposition, "filename"))
+        .or_else(|| part.headers.get_first_header("Content-ID")
+            .map(|h| format!("image_{}.jpg", h.get_value().trim_matches(|c| c == '<' || c == '>'))))
+}
+
+/// Whether a part seen in BODYSTRUCTURE would survive `extract_attachments` and the size limit.
+fn is_candidate_part(part: &PartInfo, config: &ImapConfig) -> bool {
+    let named = part.filename.is_some() || part.content_id.is_some();
+    if !config.disposition_allowed(part.disposition.as_deref(), part.content_id.is_some()) {
+        return false;
+    }
+
+    // The files inside a TNEF container are only known once it is downloaded
+    if is_tnef_part(&part.content_type, part.filename.as_deref()) {
+        return true;
+    }
+
+    categories::is_wanted(&part.content_type, &config.categories, &config.attachment_types) && named
+        && config.size_allowed(part.decoded_size()).is_ok()
+        && part.filename.as_deref().map_or(true, |filename| config.filename_allowed(filename).is_ok())
+}
+
+/// Identifies emails across folders by their Gmail message id (`X-GM-MSGID`, the same in every
+/// folder) where the server has it, otherwise by Message-ID. Emails without either are left out
+/// and never treated as duplicates.
+async fn message_keys(imap_session: &mut ImapSession, config: &ImapConfig, uids: &[u32]) -> Result<HashMap<u32, String>> {
+    let gmail = capabilities::has(imap_session, config, "X-GM-EXT-1").await?;
+    let query = if gmail { "(X-GM-MSGID ENVELOPE)" } else { "ENVELOPE" };
+    let mut keys = HashMap::new();
+
+    for chunk in uids.chunks(500) {
+        let mut messages_stream = imap_session.uid_fetch(uid_set(chunk), query).await?;
+
+        while let Some(message) = messages_stream.try_next().await? {
+            let Some(uid) = message.uid else { continue };
+            let key = match message.gmail_msg_id() {
+                Some(id) => format!("X-GM-MSGID {}", id),
+                None => match message.envelope().and_then(|envelope| envelope.message_id.as_deref()) {
+                    Some(message_id) => format!("Message-ID {}", String::from_utf8_lossy(message_id).trim()),
+                    None => continue,
+                },
+            };
+            keys.insert(uid, key);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Fetches only BODYSTRUCTURE for the given messages and keeps those with at least one
+/// attachment that would be saved, so bodies are never downloaded for the rest.
+async fn prefilter_uids(imap_session: &mut ImapSession, config: &ImapConfig, uids: &[u32]) -> Result<HashSet<u32>> {
+    let mut wanted = HashSet::new();
+
+    for chunk in uids.chunks(500) {
+        let mut messages_stream = imap_session.uid_fetch(uid_set(chunk), "BODYSTRUCTURE").await?;
+
+        while let Some(message) = messages_stream.try_next().await? {
+            let (Some(uid), Some(structure)) = (message.uid, message.bodystructure()) else { continue };
+
+            if bodystructure::parts(structure).iter().any(|part| is_candidate_part(part, config)) {
+                wanted.insert(uid);
+            }
+        }
+    }
+
+    Ok(wanted)
+}
+
+fn transfer_encoding(part: &mailparse::ParsedMail<'_>) -> String {
+    part.headers.get_first_value("Content-Transfer-Encoding").unwrap_or_default()
+}
+
+/// The content of a part as it is in the message, still transfer-encoded.
+fn encoded_body(part: &mailparse::ParsedMail<'_>) -> Vec<u8> {
+    use mailparse::body::Body;
+
+    match part.get_body_encoded() {
+        Body::Base64(body) | Body::QuotedPrintable(body) => body.get_raw().to_vec(),
+        Body::SevenBit(body) | Body::EightBit(body) => body.get_raw().to_vec(),
+        Body::Binary(body) => body.get_raw().to_vec(),
+    }
+}
+
+/// Undoes the transfer encoding of an attachment. If that fails, the attachment is skipped,
+/// or with `assume_binary` saved exactly as it came, so it can still be recovered by hand.
+fn decode_content(encoding: &str, data: &[u8], filename: &str, config: &ImapConfig) -> Option<Vec<u8>> {
+    match transfer::decode(encoding, data) {
+        Ok(decoded) => Some(decoded),
+        Err(e) if config.assume_binary => {
+            status!(Status::Warning, "{}: {:#}, saved without decoding", filename, e);
+            Some(data.to_vec())
+        }
+        Err(e) => {
+            status!(Status::Warning, "{}: {:#}, skipped (--assume-binary saves it undecoded)", filename, e);
+            None
+        }
+    }
+}
+
+fn part_content(part: &mailparse::ParsedMail<'_>, filename: &str, config: &ImapConfig) -> Option<Vec<u8>> {
+    decode_content(&transfer_encoding(part), &encoded_body(part), filename, config)
+}
+
+/// Whether a part is an Outlook TNEF container, by its type or its usual `winmail.dat` name.
+fn is_tnef_part(content_type: &str, filename: Option<&str>) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime == "application/ms-tnef" || mime == "application/vnd.ms-tnef"
+        || filename.is_some_and(|filename| filename.eq_ignore_ascii_case("winmail.dat"))
+}
+
+/// Whether a file taken out of a container passes the type filters, judged by its extension.
+fn embedded_file_wanted(filename: &str, config: &ImapConfig) -> bool {
+    categories::is_wanted(categories::type_from_filename(filename), &config.categories, &config.attachment_types)
+}
+
+/// The wanted files inside a TNEF container. A container that cannot be read is kept as it
+/// is if its own type is wanted, so nothing is lost silently.
+fn tnef_attachments(filename: &str, data: Vec<u8>, config: &ImapConfig) -> Vec<EmailAttachment> {
+    let files = if tnef::is_tnef(&data) { tnef::attachments(&data) } else { Err(anyhow::anyhow!("no TNEF signature")) };
+    match files {
+        Ok(files) => files.into_iter()
+            .filter(|file| embedded_file_wanted(&file.filename, config))
+            .map(|file| EmailAttachment { filename: file.filename, data: file.data, container: Some(filename.to_string()) })
+            .collect(),
+        Err(e) => {
+            status!(Status::Warning, "cannot read {}: {:#}", filename, e);
+            if categories::is_wanted("application/ms-tnef", &config.categories, &config.attachment_types) {
+                vec![EmailAttachment { filename: filename.to_string(), data, container: None }]
+            } else {
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn extract_attachments(part: &mailparse::ParsedMail<'_>, config: &ImapConfig) -> Vec<EmailAttachment> {
+    let mut attachments = Vec::new();
+
+    // mailparse reports a missing Content-Disposition as inline, so look at the header itself
+    let disposition = part.headers.get_first_header("Content-Disposition")
+        .map(|_| match part.get_content_disposition().disposition {
+            mailparse::DispositionType::Attachment => "attachment".to_string(),
+            mailparse::DispositionType::Inline => "inline".to_string(),
+            mailparse::DispositionType::FormData => "form-data".to_string(),
+            mailparse::DispositionType::Extension(other) => other.to_lowercase(),
+        });
+    let has_content_id = part.headers.get_first_header("Content-ID").is_some();
+
+    let content_type = get_content_type(part);
+    let filename = get_filename(part);
+
+    // Outlook's winmail.dat and uuencoded bodies carry files without MIME headers of their own
+    if is_tnef_part(content_type.as_deref().unwrap_or_default(), filename.as_deref()) {
+        if let Some(data) = part_content(part, filename.as_deref().unwrap_or("winmail.dat"), config) {
+            attachments.extend(tnef_attachments(filename.as_deref().unwrap_or("winmail.dat"), data, config));
+            return attachments;
+        }
+    }
+    if part.subparts.is_empty() && disposition.as_deref() != Some("attachment")
+        && content_type.as_deref().map_or(true, |content_type| content_type.starts_with("text/plain")) {
+        if let Ok(body) = transfer::decode(&transfer_encoding(part), &encoded_body(part)) {
+            if uuencode::contains_begin(&body) {
+                attachments.extend(uuencode::files(&body).into_iter()
+                    .filter(|file| embedded_file_wanted(&file.filename, config))
+                    .map(|file| EmailAttachment { filename: file.filename, data: file.data, container: None }));
+            }
+        }
+    }
+
+    // Check if this part is in one of the selected categories or types
+    if let Some(content_type) = content_type {
+        if categories::is_wanted(&content_type, &config.categories, &config.attachment_types)
+            && config.disposition_allowed(disposition.as_deref(), has_content_id) {
+            if let Some(filename) = filename {
+                if let Some(data) = part_content(part, &filename, config) {
+                    attachments.push(EmailAttachment {
+                        filename,
+                        data,
+                        container: None,
+                    });
+                }
+            }
+        }
+    }
+
+    // Check subparts
+    for subpart in &part.subparts {
+        attachments.extend(extract_attachments(subpart, config));
+    }
+
+    attachments
+}
+
+/// An email as it came from the server: either the whole message or, with `fetch_parts`,
+/// only its header and the already decoded attachment parts.
+enum FetchedMessage {
+    Full(Vec<u8>),
+    Parts {
+        header: Vec<u8>,
+        attachments: Vec<EmailAttachment>,
+        size: usize,
+    },
+}
+
+impl FetchedMessage {
+    fn size(&self) -> usize {
+        match self {
+            FetchedMessage::Full(data) => data.len(),
+            FetchedMessage::Parts { size, .. } => *size,
+        }
+    }
+}
+
+fn header_mismatch(source: &MessageSource) {
+    status!(Status::Skipped, "email #{} in {} (headers do not match the filters)", source.uid, source.mailbox);
+    report::message_processed();
+}
+
+/// Saves what the configuration keeps of an email. Returns whether it matched the filters and
+/// something of it is now on disk, as only those emails may be marked, moved or deleted.
+#[tracing::instrument(name = "message", skip_all, fields(mailbox = %source.mailbox, uid = source.uid))]
+async fn process_message(source: MessageSource, message: FetchedMessage, config: &ImapConfig) -> Result<bool> {
+    match message {
+        FetchedMessage::Full(data) => {
+            let parsed = mailparse::parse_mail(&data).context(ErrorCode::Parse)?;
+            if !filters::headers_match(config, &parsed.headers)? {
+                header_mismatch(&source);
+                return Ok(false);
+            }
+            let mut kept = false;
+            if config.save_eml || config.eml_only {
+                save_eml(&source, &parsed.headers, &data, config).await?;
+                kept = true;
+            }
+            if config.save_body != SaveBody::None && !config.eml_only {
+                kept |= save_body(&source, &parsed, config).await?;
+            }
+
+            let attachments = if config.eml_only { Vec::new() } else { extract_attachments(&parsed, config) };
+            Ok(save_attachments(source, &parsed.headers, attachments, data.len(), config).await? || kept)
+        }
+        FetchedMessage::Parts { header, attachments, size } => {
+            let (headers, _) = mailparse::parse_headers(&header).context(ErrorCode::Parse)?;
+            if !filters::headers_match(config, &headers)? {
+                header_mismatch(&source);
+                return Ok(false);
+            }
+            save_attachments(source, &headers, attachments, size, config).await
+        }
+    }
+}
+
+/// Folder the attachments of an email are saved into: `download_dir`, or its thread's folder with `group_by_thread`.
+fn message_dir(source: &MessageSource, subject: Option<&str>, config: &ImapConfig) -> Result<PathBuf> {
+    match source.thread_id {
+        Some(thread_id) if config.group_by_thread => threads::thread_dir(&config.download_dir, thread_id, subject.unwrap_or_default()),
+        _ => Ok(config.download_dir.clone()),
+    }
+}
+
+/// Saves the message text selected by `save_body` as `{uid}-body.txt` / `{uid}-body.html` next to the attachments,
+/// returning whether the email had any.
+async fn save_body(source: &MessageSource, parsed: &mailparse::ParsedMail<'_>, config: &ImapConfig) -> Result<bool> {
+    let dir = message_dir(source, parsed.headers.get_first_value("Subject").as_deref(), config)?;
+    let bodies = body::extract(parsed, config.save_body);
+    let found = !bodies.is_empty();
+    for (extension, text) in bodies {
+        let filename = format!("{}-body.{}", source.uid, extension);
+        save_attachment(text.as_bytes(), &filename, &dir, config.on_existing).await?;
+    }
+    Ok(found)
+}
+
+/// Keeps the original email as `{uid}-{subject-slug}.eml` in `eml_dir` for provenance.
+async fn save_eml(source: &MessageSource, headers: &[mailparse::MailHeader<'_>], data: &[u8], config: &ImapConfig) -> Result<()> {
+    let subject_slug = template::slugify(&headers.get_first_value("Subject").unwrap_or_default());
+    let filename = match subject_slug.as_str() {
+        "" => format!("{}.eml", source.uid),
+        slug => format!("{}-{}.eml", source.uid, slug),
+    };
+
+    save_attachment(data, &filename, &config.download_dir.join(&config.eml_dir), config.on_existing).await?;
+    Ok(())
+}
+
+/// Address of the first `From` mailbox, lowercased.
+fn sender_address(headers: &[mailparse::MailHeader<'_>]) -> Option<String> {
+    let from = headers.get_first_header("From")?;
+    let addresses = mailparse::addrparse_header(from).ok()?;
+    let address = match addresses.first()? {
+        mailparse::MailAddr::Single(single) => single.addr.clone(),
+        mailparse::MailAddr::Group(group) => group.addrs.first()?.addr.clone(),
+    };
+    Some(address.to_lowercase())
+}
+
+fn message_date(headers: &[mailparse::MailHeader<'_>]) -> Option<chrono::DateTime<chrono::Utc>> {
+    let date = mailparse::dateparse(&headers.get_first_value("Date")?).ok()?;
+    chrono::DateTime::from_timestamp(date, 0)
+}
+
+/// Placeholders `filename_template_vars` provides, plus those filled in per attachment.
+const FILENAME_PLACEHOLDERS: &[&str] = &[
+    "filename", "original_name", "index", "hash", "date", "uid", "from_user", "from_domain", "subject_slug",
+];
+
+/// Placeholders available to `filename_template` that are the same for every attachment of an email.
+fn filename_template_vars(source: &MessageSource, headers: &[mailparse::MailHeader<'_>]) -> HashMap<&'static str, String> {
+    let sender = sender_address(headers);
+    let (from_user, from_domain) = match sender.as_deref().and_then(|sender| sender.rsplit_once('@')) {
+        Some((user, domain)) => (user.to_string(), domain.to_string()),
+        None => (sender.unwrap_or_else(|| "unknown".to_string()), "unknown".to_string()),
+    };
+    let subject_slug = template::slugify(&headers.get_first_value("Subject").unwrap_or_default());
+    let date = message_date(headers)
+        .map_or_else(|| "unknown".to_string(), |date| date.with_timezone(&Local).format("%Y-%m-%d").to_string());
+
+    HashMap::from([
+        ("date", date),
+        ("uid", source.uid.to_string()),
+        ("from_user", from_user),
+        ("from_domain", from_domain),
+        ("subject_slug", subject_slug),
+    ])
+}
+
+/// Placeholders `path_template_vars` provides, plus `{filename}`.
+const PATH_PLACEHOLDERS: &[&str] = &["filename", "sender", "sender_domain", "subject_slug", "mailbox", "year", "month", "day"];
+
+/// Placeholders available to `path_template`; `{filename}` is filled in per attachment.
+fn path_template_vars(source: &MessageSource, headers: &[mailparse::MailHeader<'_>]) -> HashMap<&'static str, String> {
+    let sender = sender_address(headers).unwrap_or_else(|| "unknown".to_string());
+    let sender_domain = sender.rsplit_once('@').map_or("unknown", |(_, domain)| domain).to_string();
+    let subject_slug = template::slugify(&headers.get_first_value("Subject").unwrap_or_default());
+    let date = message_date(headers).map(|date| date.with_timezone(&Local).date_naive());
+
+    let (year, month, day) = match date {
+        Some(date) => (date.format("%Y").to_string(), date.format("%m").to_string(), date.format("%d").to_string()),
+        None => ("unknown".to_string(), "unknown".to_string(), "unknown".to_string()),
+    };
+
+    HashMap::from([
+        ("sender", sender),
+        ("sender_domain", sender_domain),
+        ("subject_slug", if subject_slug.is_empty() { "no-subject".to_string() } else { subject_slug }),
+        ("mailbox", source.mailbox.clone()),
+        ("year", year),
+        ("month", month),
+        ("day", day),
+    ])
+}
+
+/// Saves the wanted attachments of an email, returning whether any of them is on disk afterwards,
+/// including ones skipped because the same file or content was already there. An email only
+/// partly picked with `--interactive` counts as not saved.
+async fn save_attachments(
+    source: MessageSource,
+    headers: &[mailparse::MailHeader<'_>],
+    mut attachments: Vec<EmailAttachment>,
+    message_size: usize,
+    config: &ImapConfig,
+) -> Result<bool> {
+    attachments.retain(|attachment| match config.filename_allowed(&attachment.filename) {
+        Ok(()) => true,
+        Err(reason) => {
+            status!(Status::Skipped, "{} ({})", attachment.filename, reason);
+            report::attachment_skipped();
+            false
+        }
+    });
+    attachments.retain(|attachment| match config.size_allowed(attachment.data.len() as u64) {
+        Ok(()) => true,
+        Err(reason) => {
+            status!(Status::Skipped, "{} ({} {})", attachment.filename, format_size(attachment.data.len() as u64), reason);
+            report::attachment_skipped();
+            false
+        }
+    });
+
+    // An email with attachments left unpicked is not done, so later runs still offer them
+    let mut partly_picked = false;
+    if let Some(selection) = &config.selection {
+        let picked = selection.get(&source.mailbox).and_then(|uids| uids.get(&source.uid));
+        let wanted = attachments.len();
+        attachments.retain(|attachment| picked.is_some_and(|names| {
+            names.contains(attachment.container.as_ref().unwrap_or(&attachment.filename))
+        }));
+        partly_picked = attachments.len() < wanted;
+    }
+
+    if let Some(max_count) = config.max_attachments_per_message {
+        if attachments.len() > max_count {
+            status!(Status::Warning, "Message has {} attachments, keeping only the first {}", attachments.len(), max_count);
+            attachments.truncate(max_count);
+        }
+    }
+
+    let subject = headers.get_first_value("Subject");
+    let message_id = headers.get_first_value("Message-ID");
+    let sender = headers.get_first_value("From");
+    let date = message_date(headers);
+
+    let target_dir = message_dir(&source, subject.as_deref(), config)?;
+
+    let mut name_vars = filename_template_vars(&source, headers);
+    let mut path_vars = path_template_vars(&source, headers);
+
+    let mut saved = 0;
+    let mut on_disk = false;
+
+    for (index, attachment) in attachments.into_iter().enumerate() {
+        let safe_filename = filenames::sanitize(&attachment.filename, config.filename_sanitization);
+        let sha256 = format!("{:x}", Sha256::digest(&attachment.data));
+        let filename = match &config.filename_template {
+            Some(filename_template) => {
+                name_vars.insert("filename", safe_filename.clone());
+                name_vars.insert("original_name", safe_filename);
+                name_vars.insert("index", (index + 1).to_string());
+                name_vars.insert("hash", sha256[..8].to_string());
+                template::render_path(filename_template, &name_vars)
+            }
+            None => safe_filename,
+        };
+        let filename = match &config.path_template {
+            Some(path_template) => {
+                path_vars.insert("filename", filename);
+                template::render_path(path_template, &path_vars)
+            }
+            None => filename,
+        };
+        let filename = if config.organize_photos {
+            photos::organized_path(&filename, &attachment.data, date, &sha256[..8]).unwrap_or(filename)
+        } else {
+            filename
+        };
+        let filename = filenames::normalize(&filename, config.filename_normalization, config.ascii_filenames);
+        disk_guard::check(config, &config.download_dir, attachment.data.len() as u64)?;
+
+        if !dedup::claim(&sha256) {
+            status!(Status::Skipped, "{} (same content already saved)", attachment.filename);
+            report::attachment_deduplicated();
+            on_disk = true;
+            continue;
+        }
+
+        let span = tracing::debug_span!("attachment", filename = %filename, size = attachment.data.len());
+        let saved_path = if config.sink.is_remote() {
+            upload_attachment(&attachment.data, &filename, &target_dir, config, &path_vars).instrument(span).await
+        } else {
+            save_attachment(&attachment.data, &filename, &target_dir, config.on_existing).instrument(span).await
+        };
+        let saved_path = saved_path.inspect_err(|_| dedup::release(&sha256))?;
+        // Without a path the file already existed and was kept
+        on_disk = true;
+        if let Some(path) = saved_path {
+            let local = !config.sink.is_remote();
+            let extracted = if local && config.extract_archives {
+                extract_archive(&attachment.data, &path, &target_dir, config).await?
+            } else {
+                None
+            };
+            logging::progress_saved(attachment.data.len() as u64);
+            disk_guard::saved(attachment.data.len() as u64);
+
+            // With `delete_extracted_archives` only the files taken out of the archive remain
+            let mut files = Vec::new();
+            if extracted.is_none() || !config.delete_extracted_archives {
+                if let Some(date) = date.filter(|_| local && config.preserve_email_date) {
+                    if let Err(e) = set_file_date(&path, date) {
+                        status!(Status::Warning, "cannot set the date of {:?}: {:#}", path, e);
+                    }
+                }
+                if local && config.write_xattrs {
+                    xattrs::write(&path, &xattrs::Provenance {
+                        message_id: message_id.as_deref(),
+                        sender: sender.as_deref(),
+                        subject: subject.as_deref(),
+                    });
+                }
+                if local {
+                    if let Err(e) = checksums::write(config.checksums, &config.download_dir, &path, &sha256) {
+                        status!(Status::Warning, "cannot write the checksum of {:?}: {:#}", path, e);
+                    }
+                }
+
+                events::emit(Event::AttachmentSaved {
+                    mailbox: &source.mailbox,
+                    uid: source.uid,
+                    filename: &attachment.filename,
+                    path: &path,
+                    size: attachment.data.len(),
+                    sha256: &sha256,
+                });
+
+                downloader::notify(SavedAttachment {
+                    mailbox: source.mailbox.clone(),
+                    uid: source.uid,
+                    filename: attachment.filename.clone(),
+                    path: path.clone(),
+                    size: attachment.data.len() as u64,
+                    sha256: sha256.clone(),
+                });
+                export::attachment(&source, &attachment.filename, &path, attachment.data.len(), &sha256);
+                saved += 1;
+                files.push(ExtractedFile {
+                    filename: attachment.filename.clone(),
+                    path,
+                    size: attachment.data.len() as u64,
+                    sha256,
+                });
+            }
+            files.extend(extracted.into_iter().flatten());
+
+            for file in files {
+                report::attachment_saved(&source.mailbox, source.uid, &file.path, file.size, &file.sha256);
+                let entry = manifest::ManifestEntry {
+                    mailbox: &source.mailbox,
+                    uid: source.uid,
+                    message_id: message_id.as_deref(),
+                    date,
+                    from: sender.as_deref(),
+                    subject: subject.as_deref(),
+                    filename: &file.filename,
+                    size: file.size,
+                    sha256: &file.sha256,
+                    path: &file.path,
+                };
+                if let Some(manifest_path) = &config.manifest {
+                    manifest::append(manifest_path, &entry)?;
+                }
+                hooks::attachment_saved(config, &entry).await;
+
+                db::attachment_saved(&source, &file.filename, &file.path, file.size, &file.sha256)?;
+                history::append(&config.state_dir.join(HISTORY_FILE), &HistoryEntry {
+                    mailbox: source.mailbox.clone(),
+                    uid_validity: source.uid_validity,
+                    uid: source.uid,
+                    filename: file.filename,
+                    path: file.path,
+                    size: file.size,
+                    sha256: file.sha256,
+                })?;
+            }
+        }
+    }
+
+    report::message_processed();
+    if !partly_picked {
+        db::message_processed(&source)?;
+    }
+    export::message(&source, headers, message_size, saved);
+    Ok(on_disk && !partly_picked)
+}
+
+/// A `[since, before)` date range restricting a search.
+type SearchWindow = (NaiveDate, NaiveDate);
+
+/// Quotes a string for use in a SEARCH key, escaping backslashes and double quotes.
+fn imap_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn imap_date(date: NaiveDate) -> String {
+    date.format("%-d-%b-%Y").to_string()
+}
+
+/// Searches for emails from or to `sender`, optionally limited to a date window and to UIDs from `min_uid` on.
+async fn search_uids(imap_session: &mut ImapSession, config: &ImapConfig, window: Option<SearchWindow>, min_uid: Option<u32>) -> Result<HashSet<u32>> {
+    let mut criteria = window
+        .map(|(since, before)| format!(" SINCE {} BEFORE {}", imap_date(since), imap_date(before)))
+        .unwrap_or_default();
+    if let Some(since) = config.since {
+        criteria.push_str(&format!(" SINCE {}", imap_date(since)));
+    }
+    if let Some(before) = config.before {
+        criteria.push_str(&format!(" BEFORE {}", imap_date(before)));
+    }
+    if let Some(min_uid) = min_uid {
+        criteria.push_str(&format!(" UID {}:*", min_uid));
+    }
+    // Parenthesized so its own OR/NOT keys cannot swallow the other criteria
+    if let Some(raw_query) = &config.raw_query {
+        criteria.push_str(&format!(" ({})", raw_query));
+    }
+    if config.only_unseen {
+        criteria.push_str(" UNSEEN");
+    }
+    if config.only_flagged {
+        criteria.push_str(" FLAGGED");
+    }
+    if config.skip_deleted {
+        criteria.push_str(" NOT DELETED");
+    }
+    if config.skip_marked {
+        if let Some(flag) = &config.mark_flag {
+            match flag.to_ascii_lowercase().as_str() {
+                "\\seen" => criteria.push_str(" UNSEEN"),
+                "\\flagged" => criteria.push_str(" UNFLAGGED"),
+                _ => criteria.push_str(&format!(" UNKEYWORD {}", flag)),
+            }
+        }
+        if let Some(label) = &config.mark_label {
+            criteria.push_str(&format!(" NOT X-GM-LABELS {}", imap_quote(label)));
+        }
+    }
+    if let Some(gmail_query) = &config.gmail_query {
+        // Searching without the query would download far more than asked for, and taking it
+        // for "no emails" would let the folder state skip them for good
+        if !capabilities::has(imap_session, config, "X-GM-EXT-1").await? {
+            return Err(anyhow::anyhow!("`gmail_query` is set but {} does not support X-GM-RAW", config.server)
+                .context(ErrorCode::Config));
+        }
+        criteria.push_str(&format!(" X-GM-RAW {}", imap_quote(gmail_query)));
+    }
+
+    let mut queries = Vec::new();
+    for sender in config.senders() {
+        if config.direction != Direction::To {
+            queries.push(format!("FROM {}", imap_quote(sender)));
+        }
+        if config.direction != Direction::From {
+            queries.push(format!("TO {}", imap_quote(sender)));
+        }
+    }
+
+    // Without senders, `raw_query` or `gmail_query` alone selects the emails
+    if queries.is_empty() && (config.raw_query.is_some() || config.gmail_query.is_some()) {
+        queries.push("ALL".to_string());
+    }
+    if queries.is_empty() {
+        status!(Status::Warning, "None of `sender`, `raw_query` or `gmail_query` is set, nothing to search");
+    }
+
+    let mut all_uids = HashSet::new();
+
+    for query in queries {
+        let search = format!("{}{}", query, criteria);
+        tracing::debug!(%search, "searching");
+        // Taking a failed search for "no emails" would let the folder state skip them for good
+        let uids = imap_session.uid_search(&search).await.with_context(|| format!("UID SEARCH {} failed", search))?;
+        info!("Found {} emails {}", uids.len(), query);
+        all_uids.extend(uids);
+    }
+
+    // `n:*` always matches the highest UID, even when it is below n
+    if let Some(min_uid) = min_uid {
+        all_uids.retain(|&uid| uid >= min_uid);
+    }
+
+    Ok(all_uids)
+}
+
+/// Date of the first message in the selected mailbox. Messages are numbered in arrival
+/// order, so this is where month-by-month searching has to start.
+async fn oldest_message_date(imap_session: &mut ImapSession) -> Result<Option<NaiveDate>> {
+    let mut messages_stream = imap_session.fetch("1", "INTERNALDATE").await?;
+    let mut oldest = None;
+
+    while let Some(message) = messages_stream.try_next().await? {
+        oldest = message.internal_date().map(|date| date.date_naive());
+    }
+
+    Ok(oldest)
+}
+
+/// Runs `future` to completion or until `limit` passes, in which case `None` is returned.
+async fn within<T>(limit: Option<Duration>, future: impl Future<Output = T>) -> Option<T> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Fetch item for a whole message: `RFC822` sets `\Seen` on the server, `BODY.PEEK[]` leaves it alone.
+fn message_query(config: &ImapConfig) -> &'static str {
+    if config.peek { "BODY.PEEK[]" } else { "RFC822" }
+}
+
+async fn fetch_message(imap_session: &mut ImapSession, config: &ImapConfig, uid: u32) -> Result<Vec<(Option<u64>, FetchedMessage)>> {
+    // X-GM-THRID is a Gmail extension, so only ask for it when it is actually needed
+    let query = if config.group_by_thread {
+        format!("({} X-GM-THRID)", message_query(config))
+    } else {
+        message_query(config).to_string()
+    };
+    let mut messages_stream = imap_session.uid_fetch(uid.to_string(), query).await?;
+    let mut messages = Vec::new();
+
+    while let Some(message) = messages_stream.try_next().await? {
+        if let Some(body) = message.body() {
+            messages.push((threads::gmail_thread_id(&message), FetchedMessage::Full(body.to_owned())));
+        }
+    }
+
+    Ok(messages)
+}
+
+fn part_filename(part: &PartInfo) -> Option<String> {
+    part.filename.clone()
+        .or_else(|| part.content_id.as_ref().map(|id| format!("image_{}.jpg", id)))
+}
+
+/// Fetches only the header and the wanted attachment parts of an email, located through its
+/// BODYSTRUCTURE, so large inline HTML and unwanted parts never cross the wire.
+async fn fetch_parts(imap_session: &mut ImapSession, config: &ImapConfig, uid: u32) -> Result<Vec<(Option<u64>, FetchedMessage)>> {
+    let query = if config.group_by_thread {
+        "(BODYSTRUCTURE RFC822.SIZE X-GM-THRID)"
+    } else {
+        "(BODYSTRUCTURE RFC822.SIZE)"
+    };
+    let mut messages_stream = imap_session.uid_fetch(uid.to_string(), query).await?;
+    let mut structure = None;
+
+    while let Some(message) = messages_stream.try_next().await? {
+        if let Some(body_structure) = message.bodystructure() {
+            let parts: Vec<PartInfo> = bodystructure::parts(body_structure)
+                .into_iter()
+                .filter(|part| is_candidate_part(part, config))
+                .collect();
+            structure = Some((threads::gmail_thread_id(&message), message.size.unwrap_or_default() as usize, parts));
+        }
+    }
+    drop(messages_stream);
+
+    let Some((thread_id, size, parts)) = structure else { return Ok(Vec::new()) };
+
+    let sections: String = parts.iter()
+        .map(|part| format!(" BODY.PEEK[{}]", part.section_string()))
+        .collect();
+    let mut messages_stream = imap_session.uid_fetch(uid.to_string(), format!("(BODY.PEEK[HEADER]{})", sections)).await?;
+    let mut messages = Vec::new();
+
+    while let Some(message) = messages_stream.try_next().await? {
+        let Some(header) = message.header() else { continue };
+
+        let attachments = parts.iter()
+            .filter_map(|part| {
+                let data = message.section(&SectionPath::Part(part.section.clone(), None))?;
+                let filename = part_filename(part).unwrap_or_default();
+                let data = decode_content(&part.encoding, data, &filename, config)?;
+                Some((part, filename, data))
+            })
+            .flat_map(|(part, filename, data)| {
+                if is_tnef_part(&part.content_type, Some(&filename)) {
+                    tnef_attachments(&filename, data, config)
+                } else if filename.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![EmailAttachment { filename, data, container: None }]
+                }
+            })
+            .collect();
+
+        messages.push((thread_id, FetchedMessage::Parts { header: header.to_vec(), attachments, size }));
+    }
+
+    Ok(messages)
+}
+
+/// A message that exceeded `message_timeout` is skipped and recorded instead of stalling the run.
+fn message_timed_out(mailbox_name: &str, uid: u32, stage: &str) {
+    let message = format!("email #{} in {} timed out while {}, skipped", uid, mailbox_name, stage);
+    status!(Status::Warning, "{}", message);
+    events::emit(Event::Error { code: ErrorCode::Timeout.as_str(), message: message.clone() });
+    report::failure(ErrorCode::Timeout, message);
+}
+
+/// Opens one more session on `mailbox_name` for the fetch pool. Not retried, as the usual
+/// reason for failing is the server's limit on concurrent connections.
+async fn open_pooled_session(config: &ImapConfig, mailbox_name: &str) -> Result<ImapSession> {
+    let mut imap_session = connect_imap(config).await?;
+    imap_session.select(mailbox_name).await?;
+    Ok(imap_session)
+}
+
+/// Awaits `future` while driving the queued processing `tasks`, collecting the results of those that finish.
+async fn while_processing<T, F: Future>(
+    future: impl Future<Output = T>,
+    tasks: &mut FuturesUnordered<F>,
+    results: &mut Vec<F::Output>,
+) -> T {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            Some(result) = tasks.next() => results.push(result),
+        }
+    }
+}
+
+/// Fetches `uids` one after another over one session, processing each fetched email concurrently
+/// with the next fetches. Each email holds part of `max_in_flight_bytes` until it is saved, so
+/// fetching pauses while parsing and saving catch up. `position` is the index of the first UID in
+/// the whole run, the run's size and its ETA, for progress lines; `sizes` are the emails' RFC822.SIZE
+/// and `keys` their identities for `dedup_messages`, recorded once an email is processed.
+/// Returns the UIDs that were handled, which are fewer than `uids` when a stop was requested.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_and_process(
+    imap_session: &mut ImapSession,
+    config: &ImapConfig,
+    mailbox_name: &str,
+    uid_validity: Option<u32>,
+    uids: &[u32],
+    sizes: &HashMap<u32, u64>,
+    keys: &HashMap<u32, String>,
+    message_timeout: Option<Duration>,
+    position: (usize, usize, &str),
+) -> Result<Vec<u32>> {
+    let (first_index, total, eta_description) = position;
+    let mut tasks = FuturesUnordered::new();
+    let mut results = Vec::new();
+    let mut handled = Vec::new();
+
+    for (i, &uid) in uids.iter().enumerate() {
+        if shutdown::requested() {
+            break;
+        }
+        let size = sizes.get(&uid).copied().unwrap_or_default();
+        // Shared by the emails of a UID, which is more than one only when a server answers oddly
+        let reservation = Arc::new(while_processing(in_flight::reserve(config, size), &mut tasks, &mut results).await);
+        status!(Status::Progress, "email #{} ({}/{}, {})", uid, first_index + i + 1, total, eta_description);
+
+        let mut throttling = throttle::Backoff::new(config.max_retries);
+        let (started, fetched) = loop {
+            throttle::before_fetch(config).await;
+            let started = Instant::now();
+            let fetch = async {
+                // An .eml or the body needs the whole message, so parts are only fetched alone when neither is kept
+                if config.fetch_parts && !config.save_eml && !config.eml_only && config.save_body == SaveBody::None {
+                    fetch_parts(&mut *imap_session, config, uid).await
+                } else {
+                    fetch_message(&mut *imap_session, config, uid).await
+                }
+            };
+
+            let fetched = while_processing(within(message_timeout, fetch), &mut tasks, &mut results).await;
+            if let Some(Err(e)) = &fetched {
+                if let Some(delay) = throttling.next(e) {
+                    status!(Status::Warning, "the server is throttling fetches: {:#}; fetching email #{} again in {} s ({})",
+                        e, uid, delay.as_secs(), throttling.describe());
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            }
+            break (started, fetched);
+        };
+
+        let Some(messages) = fetched else {
+            message_timed_out(mailbox_name, uid, "fetching");
+            // The rest of the response is still on the wire, so the session cannot be reused
+            *imap_session = retry::connect(config).await?;
+            imap_session.select(mailbox_name).await?;
+            handled.push(uid);
+            continue;
+        };
+
+        for (thread_id, message) in messages? {
+            events::emit(Event::MessageFetched { mailbox: mailbox_name, uid, size: message.size() });
+            tracing::debug!(mailbox = mailbox_name, uid, size = message.size(), elapsed_ms = started.elapsed().as_millis() as u64, "fetched");
+            throttle::fetched(config, message.size());
+            let source = MessageSource {
+                mailbox: mailbox_name.to_string(),
+                uid_validity,
+                uid: uid.into(),
+                thread_id,
+            };
+
+            // Whatever fetching left of the budget is what parsing and saving may take
+            let remaining = message_timeout.map(|limit| limit.saturating_sub(started.elapsed()));
+            let process = process_message(source, message, config);
+            let reservation = reservation.clone();
+            let key = keys.get(&uid);
+            tasks.push(async move {
+                let _reservation = reservation;
+                match within(remaining, process).await {
+                    // Only emails that matched and were saved may be marked, moved or deleted
+                    Some(result) => result.map(|kept| {
+                        if let Some(key) = key {
+                            dedup::message_processed(key);
+                        }
+                        kept.then_some(uid)
+                    }),
+                    None => {
+                        message_timed_out(mailbox_name, uid, "saving");
+                        Ok(None)
+                    }
+                }
+            });
+        }
+        handled.push(uid);
+    }
+
+    while let Some(result) = tasks.next().await {
+        results.push(result);
+    }
+    let processed: Vec<u32> = results.into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if let Err(e) = mark_processed(imap_session, config, &processed).await {
+        status!(Status::Warning, "cannot mark {} processed emails in {}: {:#}", processed.len(), mailbox_name, e);
+    }
+    if let Err(e) = after_download::apply(imap_session, config, &processed).await {
+        status!(Status::Warning, "cannot apply after_download = \"{}\" to {} emails in {}: {:#}",
+            config.after_download, processed.len(), mailbox_name, e);
+    }
+    Ok(handled)
+}
+
+/// Sets `mark_flag` and applies `mark_label` to emails whose attachments were saved.
+async fn mark_processed(imap_session: &mut ImapSession, config: &ImapConfig, uids: &[u32]) -> Result<()> {
+    if uids.is_empty() {
+        return Ok(());
+    }
+
+    let mut changes = Vec::new();
+    if let Some(flag) = &config.mark_flag {
+        changes.push(format!("+FLAGS.SILENT ({})", flag));
+    }
+    if let Some(label) = &config.mark_label {
+        changes.push(format!("+X-GM-LABELS ({})", imap_quote(label)));
+    }
+
+    for change in changes {
+        // The server answers with the new flags, which have to be read off the wire
+        let _: Vec<_> = imap_session.uid_store(uid_set(uids), &change).await?.try_collect().await?;
+    }
+    Ok(())
+}
+
+async fn process_uids(
+    imap_session: &mut ImapSession,
+    config: &ImapConfig,
+    mailbox_name: &str,
+    uid_validity: Option<u32>,
+    progress: &mut RunProgress,
+    resume_path: &Path,
+) -> Result<()> {
+    report::mailbox_searched(mailbox_name, uid_validity, &progress.pending);
+    let mut uids_vec = progress.remaining();
+
+    // An email only counts as a duplicate once a copy in another folder was processed successfully,
+    // so a copy that fails or times out leaves the others to be tried
+    let mut keys = HashMap::new();
+    if config.dedup_messages && !uids_vec.is_empty() {
+        keys = message_keys(imap_session, config, &uids_vec).await?;
+        let duplicates: HashSet<u32> = keys.iter()
+            .filter(|(_, key)| dedup::is_message_processed(key))
+            .map(|(&uid, _)| uid)
+            .collect();
+        if !duplicates.is_empty() {
+            info!("-- Skipping {} emails already processed from another folder", duplicates.len());
+            progress.completed.extend(uids_vec.iter().filter(|uid| duplicates.contains(uid)));
+            uids_vec.retain(|uid| !duplicates.contains(uid));
+        }
+    }
+
+    if config.prefilter {
+        let wanted = prefilter_uids(imap_session, config, &uids_vec).await?;
+        info!("-- Prefilter kept {} of {} emails", wanted.len(), uids_vec.len());
+        progress.completed.extend(uids_vec.iter().filter(|uid| !wanted.contains(uid)));
+        uids_vec.retain(|uid| wanted.contains(uid));
+    }
+
+    let mut eta = Eta::new(fetch_sizes(imap_session, &uids_vec).await?);
+    info!("Processing {} emails, ~{}", uids_vec.len(), format_size(eta.total_bytes()));
+    check_free_space(&config.download_dir, eta.total_bytes())?;
+
+    progress.save(resume_path)?;
+    logging::progress_start(uids_vec.len() as u64);
+
+    let message_timeout = (config.message_timeout > 0).then(|| Duration::from_secs(config.message_timeout));
+    let batch_size = 10;
+
+    // Extra sessions are only worth opening when there is more than one batch to share
+    let mut extra_sessions = Vec::new();
+    if uids_vec.len() > batch_size {
+        for _ in 1..config.connections {
+            match open_pooled_session(config, mailbox_name).await {
+                Ok(session) => extra_sessions.push(session),
+                Err(e) => {
+                    status!(Status::Warning, "cannot open another connection, continuing with {}: {:#}", extra_sessions.len() + 1, e);
+                    break;
+                }
+            }
+        }
+    }
+    let mut pool: Vec<&mut ImapSession> = std::iter::once(&mut *imap_session).chain(extra_sessions.iter_mut()).collect();
+
+    // Every session fetches its own share of a batch while the others do the same
+    let chunk_size = batch_size * pool.len();
+    let mut stopped = None;
+    for (chunk_index, chunk) in uids_vec.chunks(chunk_size).enumerate() {
+        if shutdown::requested() {
+            stopped = Some(shutdown::interrupted());
+            break;
+        }
+        let share = chunk.len().div_ceil(pool.len());
+        let eta_description = eta.describe();
+        let workers = pool.iter_mut()
+            .zip(chunk.chunks(share).enumerate())
+            .map(|(session, (share_index, uids))| fetch_and_process(
+                session, config, mailbox_name, uid_validity, uids, eta.sizes(), &keys, message_timeout,
+                (chunk_index * chunk_size + share_index * share, uids_vec.len(), &eta_description),
+            ));
+
+        let results = futures::future::join_all(workers).await;
+
+        // Part of the batch may not have been fetched, so only what was handled is marked as completed.
+        // A limit of `disk_guard` stops the run by failing a save, so that error is kept as the reason.
+        if shutdown::requested() {
+            let mut reason = None;
+            for result in results {
+                match result {
+                    Ok(handled) => progress.completed.extend(handled),
+                    Err(e) => reason = reason.or(Some(e)),
+                }
+            }
+            progress.save(resume_path)?;
+            stopped = Some(reason.unwrap_or_else(shutdown::interrupted));
+            break;
+        }
+        results.into_iter().collect::<Result<Vec<_>>>()?;
+
+        progress.completed.extend(chunk);
+        progress.save(resume_path)?;
+        eta.complete(chunk);
+        logging::progress_advance(chunk.len() as u64);
+    }
+
+    drop(pool);
+    for mut session in extra_sessions {
+        let _ = session.logout().await;
+    }
+
+    logging::progress_finish();
+    match stopped {
+        Some(reason) => Err(reason),
+        None => Ok(()),
+    }
+}
+
+async fn download_folder(imap_session: &mut ImapSession, config: &ImapConfig, mailbox_name: &str) -> Result<()> {
+    tokio::fs::create_dir_all(&config.download_dir).await?;
+
+    let condstore = capabilities::has(imap_session, config, "CONDSTORE").await?;
+    let mailbox = if condstore {
+        imap_session.select_condstore(mailbox_name).await?
+    } else {
+        imap_session.select(mailbox_name).await?
+    };
+    let uid_validity = mailbox.uid_validity;
+    let resume_path = RunProgress::path_for(&config.state_dir, mailbox_name);
+    let resume_path = resume_path.as_path();
+    let sync_path = FolderState::path_for(&config.state_dir, mailbox_name);
+
+    let previous = FolderState::load(&sync_path).filter(|state| state.applies_to(config, uid_validity));
+
+    // With CONDSTORE only messages added or modified since the last completed run need a look
+    let changed = match &previous {
+        Some(FolderState { highest_modseq: Some(modseq), .. }) if condstore => {
+            let changed = sync::changed_since(imap_session, *modseq).await?;
+            info!("-- {} emails changed in {} since the last run", changed.len(), mailbox_name);
+            Some(changed)
+        }
+        _ => None,
+    };
+
+    // Otherwise only emails that arrived after the last completed run are searched
+    let min_uid = match (&changed, &previous) {
+        (None, Some(FolderState { highest_uid: Some(highest_uid), .. })) => Some(highest_uid + 1),
+        _ => None,
+    };
+    // Emails the database knows as processed are skipped unless everything is searched again
+    let processed = if config.incremental { db::processed_uids(mailbox_name, uid_validity)? } else { HashSet::new() };
+    let only_changed = |mut uids: HashSet<u32>| {
+        if let Some(changed) = &changed {
+            uids.retain(|uid| changed.contains(uid));
+        }
+        uids.retain(|uid| !processed.contains(uid));
+        if let Some(selection) = &config.selection {
+            uids.retain(|uid| selection.get(mailbox_name).is_some_and(|picked| picked.contains_key(uid)));
+        }
+        uids
+    };
+
+    // A picked selection is downloaded as it is, not mixed with an interrupted run
+    let resumed = RunProgress::load(resume_path, mailbox_name, uid_validity).filter(|_| config.selection.is_none());
+    if let Some(progress) = &resumed {
+        info!("-- Resuming interrupted run: {} of {} emails already processed",
+            progress.completed.len(), progress.pending.len());
+    }
+
+    if !config.monthly_search {
+        let mut progress = match resumed {
+            Some(progress) => progress,
+            None => {
+                let uids = only_changed(search_uids(imap_session, config, None, min_uid).await?);
+                RunProgress::new(mailbox_name, uid_validity, config.order.sort(uids))
+            }
+        };
+        process_uids(imap_session, config, mailbox_name, uid_validity, &mut progress, resume_path).await?;
+    } else {
+        let mut resumed = resumed.filter(|progress| progress.window.is_some());
+
+        let mut month = match &resumed {
+            Some(progress) => progress.window.unwrap(),
+            None if mailbox.exists == 0 => return Ok(()),
+            None => match oldest_message_date(imap_session).await? {
+                // Months before `since` would only be searched to find nothing
+                Some(date) => date.max(config.since.unwrap_or(date)).with_day(1).unwrap(),
+                None => return Ok(()),
+            },
+        };
+
+        let last_day = config.before.unwrap_or(Local::now().date_naive());
+        while month <= last_day {
+            let next_month = month + Months::new(1);
+
+            // A checkpoint between windows has no pending UIDs yet, so that window is searched again
+            let mut progress = match resumed.take() {
+                Some(progress) if !progress.pending.is_empty() => progress,
+                _ => {
+                    let uids = only_changed(search_uids(imap_session, config, Some((month, next_month)), min_uid).await?);
+                    RunProgress::new(mailbox_name, uid_validity, config.order.sort(uids))
+                }
+            };
+            progress.window = Some(month);
+
+            if !progress.pending.is_empty() {
+                info!("-- Processing {}", month.format("%B %Y"));
+                process_uids(imap_session, config, mailbox_name, uid_validity, &mut progress, resume_path).await?;
+            }
+
+            month = next_month;
+            let mut checkpoint = RunProgress::new(mailbox_name, uid_validity, Vec::new());
+            checkpoint.window = Some(month);
+            checkpoint.save(resume_path)?;
+        }
+    }
+
+    RunProgress::clear(resume_path)?;
+    // Emails left out of a picked selection still count as new for the next run
+    if config.selection.is_none() {
+        FolderState {
+            uid_validity,
+            highest_modseq: mailbox.highest_modseq,
+            // Everything below UIDNEXT at selection time has been searched
+            highest_uid: mailbox.uid_next.map(|uid_next| uid_next.saturating_sub(1)),
+            since: config.since,
+            before: config.before,
+        }.save(&sync_path)?;
+    }
+
+    Ok(())
+}
+
+/// Folders a run goes through: `folders` if set, otherwise All Mail (or Sent), plus Spam and Trash on request.
+async fn select_folders(imap_session: &mut ImapSession, config: &ImapConfig) -> Result<Vec<String>> {
+    // Mail sent to someone lives in Sent; All Mail also works but has to wade through the whole archive
+    let folder_flags: &[&str] = match config.direction {
+        Direction::To => &["\\Sent", "\\All"],
+        _ => &["\\All"],
+    };
+
+    let folders = folders::list(imap_session).await?;
+    let mut sources = Vec::new();
+
+    for pattern in &config.folders {
+        // An exact name is searched even if exclude_folders would skip it
+        let matched: Vec<&str> = if folders.iter().any(|folder| folder.name == *pattern) {
+            vec![pattern.as_str()]
+        } else {
+            folders.iter()
+                .filter(|folder| folder.is_selectable() && !folder.is_excluded(&config.exclude_folders))
+                .filter(|folder| folders::matches_glob(pattern, &folder.name))
+                .map(|folder| folder.name.as_str())
+                .collect()
+        };
+
+        if matched.is_empty() {
+            return Err(anyhow::anyhow!("No folder matches \"{}\" from `folders`", pattern)
+                .context(ErrorCode::FolderNotFound));
+        }
+
+        for name in matched {
+            if !sources.iter().any(|source| source == name) {
+                sources.push(name.to_string());
+            }
+        }
+    }
+
+    if config.folders.is_empty() {
+        let primary = folder_flags.iter().find_map(|&flag| {
+            folders.iter()
+                .find(|folder| !folder.is_excluded(&config.exclude_folders) && folder.has_attribute(flag))
+                .map(|folder| (flag, folder))
+        });
+
+        if let Some((flag, folder)) = primary {
+            info!("-- Found \"{}\" folder: {}", flag, folder.name);
+            sources.push(folder.name.clone());
+        }
+    }
+
+    for folder in &folders {
+        // Explicitly requested special folders win over exclude_folders
+        let recovery = (config.include_spam && folder.has_attribute("\\Junk"))
+            || (config.include_trash && folder.has_attribute("\\Trash"));
+
+        if recovery && !sources.contains(&folder.name) {
+            info!("-- Including \"{}\" for recovery", folder.name);
+            sources.push(folder.name.clone());
+        }
+    }
+
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No folder to search: no All Mail folder found and `folders` is empty")
+            .context(ErrorCode::FolderNotFound));
+    }
+
+    Ok(sources)
+}
+
+async fn sweep(config: &ImapConfig) -> Result<()> {
+    let mut imap_session = retry::connect(config).await?;
+
+    let mut stop_reason = None;
+    for mailbox_name in select_folders(&mut imap_session, config).await? {
+        if shutdown::requested() {
+            break;
+        }
+        let folder_config = config.for_folder(&mailbox_name);
+        let mut backoff = retry::Backoff::new(config.max_retries);
+
+        // Progress is saved after every batch, so a retried folder resumes where the connection dropped
+        loop {
+            let Err(e) = download_folder(&mut imap_session, &folder_config, &mailbox_name).await else { break };
+            if shutdown::requested() {
+                stop_reason = Some(e);
+                break;
+            }
+            let Some(delay) = backoff.next(&e) else { return Err(e) };
+
+            status!(Status::Warning, "connection lost in {}: {:#}; reconnecting in {} s ({})",
+                mailbox_name, e, delay.as_secs(), backoff.describe());
+            tokio::time::sleep(delay).await;
+            imap_session = retry::connect(config).await?;
+        }
+    }
+
+    if shutdown::requested() {
+        info!("-- Stopped, logging out");
+        imap_session.logout().await?;
+        return Err(stop_reason.unwrap_or_else(shutdown::interrupted));
+    }
+
+    info!("-- All messages processed, logging out");
+    imap_session.logout().await?;
+    Ok(())
+}
+
+pub async fn download_attachments(config: &ImapConfig) -> Result<()> {
+    let started = Instant::now();
+    events::emit(Event::RunStarted);
+    report::start(serde_json::to_value(config)?);
+    db::open(&config.db_path())?;
+    if config.dedup {
+        let mut hashes = db::hashes()?;
+        hashes.extend(history::load(&config.state_dir.join(HISTORY_FILE))?.into_iter().map(|entry| entry.sha256));
+        dedup::init(Some(hashes));
+    } else {
+        dedup::init(None);
+    }
+    dedup::init_messages(config.dedup_messages);
+    if config.export_parquet {
+        export::start();
+    }
+    disk_guard::start();
+    let download_dir = config.download_dir.clone();
+    let removed = tokio::task::spawn_blocking(move || remove_stale_parts(&download_dir)).await??;
+    if removed > 0 {
+        info!("-- Removed {} partly written files left by an earlier run", removed);
+    }
+
+    let result = match config.protocol {
+        Protocol::Imap => sweep(config).await,
+        Protocol::Pop3 => pop3::sweep(config).await,
+        Protocol::GmailApi => gmail_api::sweep(config).await,
+    };
+
+    logging::progress_finish();
+    if let Err(e) = &result {
+        report::failure(errors::classify(e), format!("{:#}", e));
+    }
+    if let Some(summary) = report::summary() {
+        info!("-- {}", summary);
+    }
+    // Failing to write the report must not replace the run's own outcome
+    match report::finish(&config.state_dir, result.is_ok()) {
+        Ok(Some(path)) => {
+            info!("-- Run report written to {:?}", path);
+
+            match export::finish(&path) {
+                Ok(Some((messages_path, attachments_path))) => {
+                    info!("-- Metadata exported to {:?} and {:?}", messages_path, attachments_path);
+                }
+                Ok(None) => {}
+                Err(e) => status!(Status::Warning, "cannot export the run's metadata: {:#}", e),
+            }
+        }
+        Ok(None) => {}
+        Err(e) => status!(Status::Warning, "cannot write the run report: {:#}", e),
+    }
+
+    events::emit(Event::RunFinished { duration_ms: started.elapsed().as_millis() });
+    result
+}
+
+pub fn init_logging(config: &ImapConfig, quiet: bool) -> Result<()> {
+    logging::init(quiet, events::enabled(), config.log_file.as_deref(), config.log_rotation, config.log_max_size, config.log_keep)
+}
+
+pub fn load_config(path: &Path, no_prompt: bool) -> Result<ImapConfig> {
+    let mut config: ImapConfig = match read_to_string(path) {
+        Ok(content) => toml::from_str(&content).context(ErrorCode::Config)?,
+        Err(e) if no_prompt => return Err(anyhow::anyhow!("Cannot read {:?}: {}", path, e).context(ErrorCode::Config)),
+        Err(_) => {
+            let mut config = prompt_settings()?;
+            credentials::move_to_keyring(&mut config);
+            save_config(path, &config)?;
+            config
+        }
+    };
+
+    config.config_path = path.to_path_buf();
+    Ok(config)
+}
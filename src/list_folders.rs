@@ -0,0 +1,26 @@
+use anyhow::Result;
+
+use crate::logging::info;
+use crate::{folders, retry, ImapConfig};
+
+/// Prints every folder of the account with its attributes, marking those a run skips.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    let mut imap_session = retry::connect(config).await?;
+    let folders = folders::list(&mut imap_session).await?;
+
+    let width = folders.iter().map(|folder| folder.name.len()).max().unwrap_or_default();
+    for folder in &folders {
+        let note = if !folder.is_selectable() {
+            "  (not selectable)"
+        } else if folder.is_excluded(&config.exclude_folders) {
+            "  (excluded)"
+        } else {
+            ""
+        };
+        info!("{:<width$}  {}{}", folder.name, folder.attributes.join(" "), note, width = width);
+    }
+    info!("-- {} folders", folders.len());
+
+    imap_session.logout().await?;
+    Ok(())
+}
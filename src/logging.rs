@@ -0,0 +1,345 @@
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use console::style;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing_subscriber::EnvFilter;
+
+use crate::estimate::format_size;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogRotation {
+    #[default]
+    Never,
+    /// Start a new file every day, keeping the old one as `<log_file>.<date>`.
+    Daily,
+    /// Start a new file once the current one exceeds `log_max_size` bytes.
+    Size,
+}
+
+/// How status lines and `-v` diagnostics are written to the console.
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Colored status lines, with diagnostics as plain text on stderr
+    #[default]
+    Text,
+    /// Every status line and diagnostic as one JSON object per line on stderr
+    Json,
+}
+
+/// Target of the raw protocol lines logged with `--imap-trace`.
+pub const IMAP_TRACE: &str = "imap_trace";
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    rotation: LogRotation,
+    max_size: u64,
+    keep: usize,
+    opened_on: NaiveDate,
+}
+
+/// Progress bar shown on a terminal while a folder is processed.
+struct Bar {
+    bar: ProgressBar,
+    started: Instant,
+    attachments: usize,
+    bytes: u64,
+}
+
+struct Logger {
+    quiet: bool,
+    /// Status lines are handed to `tracing` as JSON instead of being printed.
+    json: bool,
+    /// Human output goes to stderr when stdout is reserved for machine-readable events.
+    stderr: bool,
+    terminal: bool,
+    progress_pending: bool,
+    bar: Option<Bar>,
+    file: Option<LogFile>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    quiet: false,
+    json: false,
+    stderr: false,
+    terminal: false,
+    progress_pending: false,
+    bar: None,
+    file: None,
+});
+
+pub fn init(quiet: bool, stderr: bool, log_file: Option<&Path>, rotation: LogRotation, max_size: u64, keep: usize) -> Result<()> {
+    let file = match log_file {
+        Some(path) => Some(LogFile {
+            path: path.to_path_buf(),
+            file: open_append(path)?,
+            rotation,
+            max_size,
+            keep,
+            opened_on: Local::now().date_naive(),
+        }),
+        None => None,
+    };
+
+    let mut logger = LOGGER.lock().unwrap();
+    logger.quiet = quiet;
+    logger.stderr = stderr;
+    logger.terminal = if stderr { std::io::stderr().is_terminal() } else { std::io::stdout().is_terminal() };
+    logger.file = file;
+    Ok(())
+}
+
+/// Sets up `tracing` for the spans and diagnostics enabled with `-v` (debug) or `-vv` (trace), JSON
+/// output and `--imap-trace`. `RUST_LOG` overrides the levels. Without any of them nothing is installed,
+/// so the console only shows status lines.
+pub fn init_tracing(verbosity: u8, format: LogFormat, imap_trace: bool) {
+    let json = format == LogFormat::Json;
+    if verbosity == 0 && !json && !imap_trace && std::env::var_os("RUST_LOG").is_none() {
+        return;
+    }
+
+    let own_level = match verbosity {
+        0 if json => "info",
+        0 => "warn",
+        1 => "debug",
+        _ => "trace",
+    };
+    let mut directives = format!("warn,{}={}", env!("CARGO_CRATE_NAME"), own_level);
+    if imap_trace {
+        directives.push_str(&format!(",{}=trace", IMAP_TRACE));
+    }
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(directives));
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    // Fails only if a subscriber is already installed, which is then kept
+    let _ = if json { builder.json().try_init() } else { builder.try_init() };
+    LOGGER.lock().unwrap().json = json;
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+impl LogFile {
+    /// Removes the oldest `log.YYYY-MM-DD` files beyond `keep`; the dates sort by name.
+    fn prune_daily(&self) -> std::io::Result<()> {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else { return Ok(()) };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+
+        let mut rotated: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str()
+                .and_then(|file_name| file_name.strip_prefix(&prefix))
+                .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.keep);
+        for path in &rotated[..excess] {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn rotate_if_needed(&mut self) -> std::io::Result<()> {
+        let today = Local::now().date_naive();
+
+        match self.rotation {
+            LogRotation::Never => return Ok(()),
+            LogRotation::Daily if today != self.opened_on => {
+                let rotated = with_suffix(&self.path, &self.opened_on.format("%Y-%m-%d").to_string());
+                std::fs::rename(&self.path, rotated)?;
+                self.prune_daily()?;
+            }
+            LogRotation::Size if self.file.metadata()?.len() >= self.max_size => {
+                // Shift log.1 -> log.2 -> ... dropping the oldest beyond `keep`
+                for n in (1..self.keep).rev() {
+                    let from = with_suffix(&self.path, &n.to_string());
+                    if from.exists() {
+                        std::fs::rename(&from, with_suffix(&self.path, &(n + 1).to_string()))?;
+                    }
+                }
+                if self.keep > 0 {
+                    std::fs::rename(&self.path, with_suffix(&self.path, "1"))?;
+                } else {
+                    std::fs::remove_file(&self.path)?;
+                }
+            }
+            _ => return Ok(()),
+        }
+
+        self.file = open_append(&self.path)?;
+        self.opened_on = today;
+        Ok(())
+    }
+}
+
+/// Kind of a status line, used to pick its label and color on a terminal.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Info,
+    /// Transient line that is overwritten by the next one on a terminal.
+    Progress,
+    Saved,
+    Skipped,
+    Warning,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Info => "",
+            Status::Progress => "Processing",
+            Status::Saved => "Saved",
+            Status::Skipped => "Skipped",
+            Status::Warning => "Warning",
+        }
+    }
+
+    fn styled_label(self) -> String {
+        let padded = style(format!("{:>12}", self.label())).bold();
+        let colored = match self {
+            Status::Info | Status::Progress => padded.cyan(),
+            Status::Saved => padded.green(),
+            Status::Skipped => padded.yellow(),
+            Status::Warning => padded.red(),
+        };
+        colored.to_string()
+    }
+
+    /// Line-per-event format used for pipes and log files.
+    fn plain(self, message: &str) -> String {
+        match self {
+            Status::Info => message.to_string(),
+            _ => format!("{}: {}", self.label(), message),
+        }
+    }
+}
+
+fn print_terminal(out: &mut dyn Write, progress_pending: &mut bool, kind: Status, message: &str) {
+    if *progress_pending {
+        let _ = write!(out, "\r\x1b[2K");
+    }
+
+    let _ = match kind {
+        Status::Info => writeln!(out, "{}", style(message.trim_start_matches("-- ").trim_start()).bold()),
+        Status::Progress => write!(out, "{} {}", kind.styled_label(), message),
+        _ => writeln!(out, "{} {}", kind.styled_label(), message),
+    };
+    let _ = out.flush();
+
+    *progress_pending = kind == Status::Progress;
+}
+
+/// Prints a status line unless `--quiet` is set and appends it to the log file, if any.
+/// On a terminal lines are colored and progress overwrites itself; when piped every event is one plain line.
+pub fn write_status(kind: Status, message: &str) {
+    let mut logger = LOGGER.lock().unwrap();
+    let logger = &mut *logger;
+
+    if logger.json {
+        // The JSON log takes the place of the console output
+        if !logger.quiet && kind != Status::Progress {
+            tracing::info!(status = kind.label(), "{}", message.trim_start_matches("-- "));
+        }
+    } else if !logger.quiet {
+        let mut out: Box<dyn Write> = if logger.stderr {
+            Box::new(std::io::stderr().lock())
+        } else {
+            Box::new(std::io::stdout().lock())
+        };
+
+        if let Some(bar) = &logger.bar {
+            // The bar already shows which email is being processed
+            if kind != Status::Progress {
+                bar.bar.suspend(|| print_terminal(&mut out, &mut false, kind, message));
+            }
+        } else if logger.terminal {
+            print_terminal(&mut out, &mut logger.progress_pending, kind, message);
+        } else {
+            let _ = writeln!(out, "{}", kind.plain(message));
+        }
+    }
+
+    if let Some(log_file) = logger.file.as_mut() {
+        // Logging must never abort a download, so file errors are reported once to stderr and ignored
+        let result = log_file.rotate_if_needed().and_then(|_| {
+            writeln!(log_file.file, "{} {}", Local::now().format("%Y-%m-%d %H:%M:%S"), kind.plain(message.trim_start()))
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to write log file {:?}: {}", log_file.path, e);
+            logger.file = None;
+        }
+    }
+}
+
+/// Shows a progress bar over `total` emails on a terminal; does nothing when piped or quiet.
+pub fn progress_start(total: u64) {
+    let mut logger = LOGGER.lock().unwrap();
+    if !logger.terminal || logger.quiet || logger.json {
+        return;
+    }
+
+    let target = if logger.stderr { ProgressDrawTarget::stderr() } else { ProgressDrawTarget::stdout() };
+    let bar = ProgressBar::with_draw_target(Some(total), target);
+    bar.set_style(ProgressStyle::with_template("{prefix:>12.cyan.bold} [{bar:30}] {pos}/{len} emails, {msg} ({eta})")
+        .unwrap()
+        .progress_chars("=> "));
+    bar.set_prefix("Processing");
+    bar.set_message("0 attachments");
+
+    logger.bar = Some(Bar { bar, started: Instant::now(), attachments: 0, bytes: 0 });
+}
+
+pub fn progress_advance(emails: u64) {
+    if let Some(bar) = &LOGGER.lock().unwrap().bar {
+        bar.bar.inc(emails);
+    }
+}
+
+/// Counts a saved attachment into the bar's statistics.
+pub fn progress_saved(bytes: u64) {
+    if let Some(bar) = LOGGER.lock().unwrap().bar.as_mut() {
+        bar.attachments += 1;
+        bar.bytes += bytes;
+        let per_second = bar.bytes as f64 / bar.started.elapsed().as_secs_f64().max(1.0);
+        bar.bar.set_message(format!("{} attachments, {} written, {}/s",
+            bar.attachments, format_size(bar.bytes), format_size(per_second as u64)));
+    }
+}
+
+pub fn progress_finish() {
+    if let Some(bar) = LOGGER.lock().unwrap().bar.take() {
+        bar.bar.finish_and_clear();
+    }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::logging::write_status($crate::logging::Status::Info, &format!($($arg)*))
+    };
+}
+
+macro_rules! status {
+    ($kind:expr, $($arg:tt)*) => {
+        $crate::logging::write_status($kind, &format!($($arg)*))
+    };
+}
+
+pub(crate) use {info, status};
@@ -1,245 +1,274 @@
-use std::path::PathBuf;
-use anyhow::Result;
-use async_imap::{self, Session};
-use async_native_tls::{self, TlsStream};
-use futures::TryStreamExt;
-use mailparse;
-use mailparse::MailHeaderMap;
-use serde::{Serialize, Deserialize};
-use dialoguer::Input;
-use std::fs::File;
-use std::io::Write;
-use std::fs::read_to_string;
-use async_std::net::TcpStream;
-use std::collections::HashSet;
-
-#[derive(Debug)]
-struct EmailAttachment {
-    filename: String,
-    data: Vec<u8>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct ImapConfig {
-    email: String,
-    password: String,
-    sender: String,
-    download_dir: PathBuf,
-    server: String,
-}
-
-fn prompt_settings() -> Result<ImapConfig> {
-    let email: String = Input::new()
-        .with_prompt("Enter your email")
-        .interact_text()?;
-
-    let password: String = Input::new()
-        .with_prompt("Enter your password")
-        .interact_text()?;
-
-    let sender: String = Input::new()
-        .with_prompt("Enter the sender email")
-        .interact_text()?;
-
-    let server: String = Input::new()
-        .with_prompt("Enter the IMAP server (e.g., imap.gmail.com)")
-        .default("imap.gmail.com".to_string())
-        .interact_text()?;
-
-    let download_dir: String = Input::new()
-        .with_prompt("Enter the download directory")
-        .default("./downloaded_images".to_string())
-        .interact_text()?;
-
-    let config = ImapConfig {
-        email,
-        password,
-        sender,
-        server,
-        download_dir: PathBuf::from(download_dir),
-    };
-
-    let toml_string = toml::to_string(&config)?;
-    let mut file = File::create("config.toml")?;
-    file.write_all(toml_string.as_bytes())?;
-
-    Ok(config)
-}
+use std::process::ExitCode;
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+
+use gmail_file_downloader::events::{self, Event};
+use gmail_file_downloader::logging::{self, Status};
+use gmail_file_downloader::{
+    config_cmd, credentials, db, diff, download_attachments, dry_run, errors, init, init_logging, list_folders, load_config,
+    oauth, paths, picker, probe, shutdown, test_connection, validate, verify, watch, AuthMethod, ImapConfig, Protocol,
+};
+#[cfg(unix)]
+use gmail_file_downloader::daemon;
+#[cfg(windows)]
+use gmail_file_downloader::service;
+
+mod cli;
+
+use cli::{Cli, Command, ConfigAction};
+
+async fn run(cli: Cli) -> Result<()> {
+    #[cfg(windows)]
+    {
+        if cli.install_service {
+            return service::install();
+        }
+        if cli.uninstall_service {
+            return service::uninstall();
+        }
+        if cli.service {
+            return service::run();
+        }
+    }
 
-async fn connect_imap(config: &ImapConfig) -> Result<Session<TlsStream<TcpStream>>> {
-    let imap_addr = (config.server.as_str(), 993);
-    let tcp_stream = TcpStream::connect(imap_addr).await?;
-    let tls = async_native_tls::TlsConnector::new();
-    let tls_stream = tls.connect(config.server.as_str(), tcp_stream).await?;
+    // Outside systemd the daemon moves to the background before anything else happens
+    #[cfg(unix)]
+    if cli.daemon && daemon::should_detach() {
+        let pid = daemon::detach()?;
+        println!("-- Started in the background, PID {}", pid);
+        return Ok(());
+    }
 
-    let client = async_imap::Client::new(tls_stream);
-    println!("-- Connected to {}:{}", imap_addr.0, imap_addr.1);
+    let config_path = paths::config_file(cli.config.as_deref());
 
-    let imap_session = client.login(&config.email, &config.password).await.map_err(|e| e.0)?;
-    println!("-- Logged in as {}", config.email);
+    match &cli.command {
+        Some(Command::Completions { shell }) => {
+            cli::print_completions(*shell);
+            return Ok(());
+        }
+        Some(Command::Init) => return init::run(&config_path).await,
+        Some(Command::Config { action }) => {
+            return match action {
+                ConfigAction::Init => init::run(&config_path).await,
+                ConfigAction::Edit => config_cmd::edit(&config_path),
+                ConfigAction::Validate => validate::run(&config_path),
+                ConfigAction::Get { key } => config_cmd::get(&config_path, key),
+                ConfigAction::Set { key, value } => config_cmd::set(&config_path, key, value),
+                ConfigAction::EncryptPassword => config_cmd::encrypt_password(&config_path),
+                ConfigAction::StorePassword => config_cmd::store_password(&config_path),
+            };
+        }
+        _ => {}
+    }
 
-    Ok(imap_session)
-}
+    if let Some(format) = cli.events {
+        events::init(format);
+    }
+    logging::init_tracing(cli.verbose, cli.log_format, cli.imap_trace);
+
+    // The daemon handles its own signals, as SIGHUP reloads it
+    #[cfg(unix)]
+    let handles_signals = !cli.daemon;
+    #[cfg(not(unix))]
+    let handles_signals = true;
+    if handles_signals {
+        tokio::spawn(shutdown::handle_signals());
+    }
 
-async fn save_attachment(attachment: &EmailAttachment, dir: &PathBuf) -> Result<()> {
-    let path = dir.join(&attachment.filename);
-    tokio::fs::write(&path, &attachment.data).await?;
-    println!("Saved: {:?}", path);
-    Ok(())
-}
+    // With the account given on the command line no config file is needed, e.g. under cron
+    let config = if cli.email.is_some() && !cli.sender.is_empty() && !config_path.exists() {
+        ImapConfig { config_path: config_path.clone(), ..ImapConfig::default() }
+    } else {
+        load_config(&config_path, cli.no_prompt)?
+    };
 
-fn get_content_type(part: &mailparse::ParsedMail<'_>) -> Option<String> {
-    part.headers.get_first_header("Content-Type")
-        .map(|h| h.get_value().to_lowercase())
-}
+    if let Some(account) = &cli.account {
+        return run_account(&cli, config.for_account(account)?).await;
+    }
+    if !cli.all_accounts {
+        return run_account(&cli, config).await;
+    }
 
-fn get_filename(part: &mailparse::ParsedMail<'_>) -> Option<String> {
-    // Try Content-Type first
-    let mut filename = part.headers.get_first_header("Content-Type")
-        .and_then(|h| {
-            let value = h.get_value();
-            if value.contains("name=") {
-                value.split("name=")
-                    .nth(1)
-                    .map(|f| f.trim_matches('"').to_string())
-            } else {
-                None
-            }
-        });
-
-    // Then try Content-Disposition
-    if filename.is_none() {
-        filename = part.headers.get_first_header("Content-Disposition")
-            .and_then(|h| {
-                let value = h.get_value();
-                if value.contains("filename=") {
-                    value.split("filename=")
-                        .nth(1)
-                        .map(|f| f.trim_matches('"').to_string())
-                } else {
-                    None
-                }
-            });
-    }
-
-    // Finally try Content-ID
-    if filename.is_none() {
-        filename = part.headers.get_first_header("Content-ID")
-            .map(|h| format!("image_{}.jpg", h.get_value().trim_matches(|c| c == '<' || c == '>')));
-    }
-
-    filename
-}
+    let names = config.account_names();
+    if names.is_empty() {
+        bail!("--all-accounts needs [[accounts]] entries in {:?}", config_path);
+    }
+    if cli.concurrent {
+        return run_concurrently(&names).await;
+    }
 
-fn extract_attachments(part: &mailparse::ParsedMail<'_>) -> Vec<EmailAttachment> {
-    let mut attachments = Vec::new();
-
-    // Check if this part is an image
-    if let Some(content_type) = get_content_type(part) {
-        if content_type.contains("image/") || content_type.contains("/jpeg") || content_type.contains("/jpg") {
-            if let Some(filename) = get_filename(part) {
-                if let Ok(data) = part.get_body_raw() {
-                    attachments.push(EmailAttachment {
-                        filename,
-                        data,
-                    });
-                }
+    let mut failed = Vec::new();
+    for name in names {
+        logging::write_status(Status::Info, &format!("-- Account {}", name));
+        if let Err(e) = run_account(&cli, config.for_account(name)?).await {
+            if shutdown::requested() {
+                return Err(e);
             }
+            logging::write_status(Status::Warning, &format!("account {} failed: {:#}", name, e));
+            failed.push(name);
         }
     }
-
-    // Check subparts
-    for subpart in &part.subparts {
-        attachments.extend(extract_attachments(subpart));
+    if !failed.is_empty() {
+        bail!("{} of the accounts failed: {}", failed.len(), failed.join(", "));
     }
-
-    attachments
+    Ok(())
 }
 
-async fn process_message(message_data: Vec<u8>, config: &ImapConfig) -> Result<()> {
-    let parsed = mailparse::parse_mail(&message_data)?;
-    let attachments = extract_attachments(&parsed);
-    
-    for attachment in attachments {
-        save_attachment(&attachment, &config.download_dir).await?;
+/// Runs every account in its own process, as runs keep their report and progress process-wide.
+async fn run_concurrently(names: &[&str]) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--all-accounts" && arg != "--concurrent")
+        .collect();
+
+    let children = names.iter().map(|name| {
+        let mut command = tokio::process::Command::new(&exe);
+        command.args(&args).arg("--account").arg(name);
+        async move { (*name, command.status().await) }
+    });
+
+    let mut failed = Vec::new();
+    for (name, status) in futures::future::join_all(children).await {
+        if !status.is_ok_and(|status| status.success()) {
+            failed.push(name);
+        }
+    }
+    if !failed.is_empty() {
+        bail!("{} of the accounts failed: {}", failed.len(), failed.join(", "));
     }
-
     Ok(())
 }
 
-async fn download_attachments(config: &ImapConfig) -> Result<()> {
-    tokio::fs::create_dir_all(&config.download_dir).await?;
+/// Applies the command line overrides to one account's settings, resolves its credentials and starts logging.
+async fn configure(cli: &Cli, mut config: ImapConfig) -> Result<ImapConfig> {
+    let config_path = config.config_path.clone();
 
-    let mut imap_session = connect_imap(&config).await?;
-    
-    let folder_flag = "all";
+    if let Some(email) = cli.email.clone() {
+        config.email = email;
+    }
+    if !cli.sender.is_empty() {
+        config.sender = String::new();
+        config.senders = cli.sender.clone();
+    }
+    if let Some(server) = cli.server.clone() {
+        config.server = server;
+    }
+    if let Some(out) = cli.out.clone() {
+        config.download_dir = out;
+    }
+    if cli.since.is_some() {
+        config.since = cli.since;
+    }
+    if cli.before.is_some() {
+        config.before = cli.before;
+    }
+    if cli.manifest.is_some() {
+        config.manifest = cli.manifest.clone();
+    }
+    if cli.db_path.is_some() {
+        config.db_path = cli.db_path.clone();
+    }
+    if cli.min_size.is_some() {
+        config.min_size = cli.min_size;
+    }
+    if cli.max_size.is_some() {
+        config.max_size = cli.max_size;
+    }
+    if let Some(types) = cli.types.clone() {
+        // The flag says exactly what to download, so the configured categories no longer apply
+        config.categories.clear();
+        config.attachment_types = types;
+    }
 
-    {
-        let folders_stream = imap_session.list(Some(""), Some("*")).await?;
-        let folders: Vec<_> = folders_stream.try_collect().await?;
-
-        for folder in folders {
-            if folder.attributes().iter().any(|flag| format!("{:?}", flag).to_lowercase().contains(folder_flag)) {
-                println!("-- Found \"{}\" folder: {}", folder_flag, folder.name());
-                imap_session.select(folder.name()).await?;
-                break;
-            }
+    let cli_password = match &cli.password_file {
+        Some(path) => Some(credentials::read_password_file(path)?),
+        None => cli.password.clone(),
+    };
+
+    let plaintext_password = config.auth == AuthMethod::Password && config.password.is_some();
+    match config.auth {
+        AuthMethod::Password => {
+            config.password = Some(credentials::resolve_password(&config, cli_password, cli.no_prompt)?);
         }
+        AuthMethod::Oauth2 => oauth::authorize(&config, cli.no_prompt).await?,
+    }
+    config.imap_trace = cli.imap_trace;
+    config.confirm_delete = cli.yes_really_delete;
+    config.incremental &= !cli.full;
+    config.assume_binary |= cli.assume_binary;
+    config.include_spam |= cli.include_spam;
+    config.include_trash |= cli.include_trash;
+    init_logging(&config, cli.quiet)?;
+    if plaintext_password {
+        logging::write_status(Status::Warning, &format!(
+            "the password is stored in plaintext in {:?}; run `config store-password` to move it to the OS keychain", config_path));
     }
 
-    let from_query = format!("FROM \"{}\"", config.sender);
-    let to_query = format!("TO \"{}\"", config.sender);
-    
-    let mut all_sequences = HashSet::new();
+    Ok(config)
+}
 
-    if let Ok(sequences) = imap_session.search(&from_query).await {
-        println!("Found {} emails FROM {}", sequences.len(), config.sender);
-        all_sequences.extend(sequences);
-    }
+/// Loads the config file again for `--daemon`, keeping the account and the command line overrides.
+#[cfg(unix)]
+async fn reload(cli: &Cli, config_path: &std::path::Path, account: Option<&str>) -> Result<ImapConfig> {
+    let config = load_config(config_path, true)?;
+    let config = match account {
+        Some(account) => config.for_account(account)?,
+        None => config,
+    };
+    configure(cli, config).await
+}
 
-    if let Ok(sequences) = imap_session.search(&to_query).await {
-        println!("Found {} emails TO {}", sequences.len(), config.sender);
-        all_sequences.extend(sequences);
+/// Runs the requested command for one account.
+async fn run_account(cli: &Cli, config: ImapConfig) -> Result<()> {
+    // The database is all `status` needs, so it works without credentials
+    if let Some(Command::Status) = cli.command {
+        return db::print_status(&cli.db_path.clone().unwrap_or_else(|| config.db_path()));
     }
 
-    let sequences_vec: Vec<u32> = all_sequences.into_iter().collect();
-    println!("Processing {} total emails", sequences_vec.len());
+    let config = configure(cli, config).await?;
+    validate::at_startup(&config)?;
+
+    let imap_only = matches!(cli.command, Some(Command::TestConnection) | Some(Command::Probe) | Some(Command::ListFolders)) || cli.interactive;
+    if config.protocol != Protocol::Imap && (cli.watches() || cli.lists_attachments() || imap_only) {
+        bail!("watch, --daemon, --interactive, list-attachments, list-folders, test-connection and probe need protocol = \"imap\"");
+    }
 
-    // Process emails in parallel batches
-    let batch_size = 10;
-    for chunk in sequences_vec.chunks(batch_size) {
-        let mut tasks = Vec::new();
-        
-        for &seq in chunk {
-            let seq_str = seq.to_string();
-            println!("\nProcessing email #{}", seq_str);
-            
-            let mut messages_stream = imap_session.fetch(seq_str, "RFC822").await?;
-            
-            while let Ok(Some(message)) = messages_stream.try_next().await {
-                if let Some(body) = message.body() {
-                    tasks.push(process_message(body.to_owned(), config));
-                }
+    match cli.command {
+        Some(Command::Verify { repair }) => verify::run(&config, repair).await,
+        Some(Command::TestConnection) => test_connection::run(&config).await,
+        Some(Command::Probe) => probe::run(&config).await,
+        Some(Command::ListFolders) => list_folders::run(&config).await,
+        _ if cli.lists_attachments() => dry_run::run(&config).await,
+        _ if cli.interactive => picker::run(&config).await,
+        #[cfg(unix)]
+        _ if cli.daemon => {
+            let pid_file = cli.pid_file.clone().unwrap_or_else(|| config.state_dir.join(daemon::PID_FILE));
+            let (config_path, account) = (config.config_path.clone(), config.account.clone());
+            daemon::run(config, &pid_file, || reload(cli, &config_path, account.as_deref())).await
+        }
+        _ if cli.watches() => watch::run(&config).await,
+        _ => {
+            download_attachments(&config).await?;
+            if cli.diff {
+                diff::print_latest(&config.state_dir)?;
             }
+            Ok(())
         }
-        
-        futures::future::join_all(tasks).await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?;
     }
-
-    println!("-- All messages processed, logging out");
-    imap_session.logout().await?;
-    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let config = match read_to_string("config.toml") {
-        Ok(content) => toml::from_str(&content)?,
-        Err(_) => prompt_settings()?,
-    };
-
-    download_attachments(&config).await?;
-    Ok(())
+async fn main() -> ExitCode {
+    // Answers the shell's completion requests when it calls back with COMPLETE set
+    clap_complete::CompleteEnv::with_factory(Cli::command).complete();
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let code = errors::classify(&e);
+            events::emit(Event::Error { code: code.as_str(), message: format!("{:#}", e) });
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(code.exit_code())
+        }
+    }
 }
\ No newline at end of file
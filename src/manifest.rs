@@ -0,0 +1,35 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::errors::ErrorCode;
+
+/// One line of `--manifest`: a saved attachment together with the email it came from.
+#[derive(Serialize)]
+pub struct ManifestEntry<'a> {
+    pub mailbox: &'a str,
+    pub uid: u64,
+    pub message_id: Option<&'a str>,
+    pub date: Option<DateTime<Utc>>,
+    pub from: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub filename: &'a str,
+    pub size: u64,
+    pub sha256: &'a str,
+    pub path: &'a Path,
+}
+
+pub fn append(path: &Path, entry: &ManifestEntry<'_>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open manifest {:?}", path))
+        .context(ErrorCode::Io)?;
+    // One write per line so lines from concurrently saved attachments never interleave
+    file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::errors::ErrorCode;
+use crate::logging::info;
+use crate::{credentials, ImapConfig};
+
+const TOKEN_FILE: &str = "oauth_token.json";
+
+/// OAuth2 client settings, defaulting to Google's endpoints. Create a "Desktop app" client
+/// in the Google Cloud console to get a client id and secret.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OAuth2Config {
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default = "default_auth_url")]
+    pub auth_url: String,
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    /// SASL mechanism presenting the access token; Gmail supports both, other servers often only OAUTHBEARER.
+    #[serde(default)]
+    pub mechanism: SaslMechanism,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SaslMechanism {
+    #[default]
+    Xoauth2,
+    Oauthbearer,
+}
+
+impl SaslMechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            SaslMechanism::Xoauth2 => "XOAUTH2",
+            SaslMechanism::Oauthbearer => "OAUTHBEARER",
+        }
+    }
+}
+
+fn default_auth_url() -> String {
+    "https://accounts.google.com/o/oauth2/v2/auth".to_string()
+}
+
+fn default_token_url() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+fn default_scope() -> String {
+    "https://mail.google.com/".to_string()
+}
+
+/// Tokens cached next to config.toml so the browser flow is only needed once.
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    email: String,
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: Option<String>,
+}
+
+/// Presents an access token with SASL XOAUTH2 (https://developers.google.com/gmail/imap/xoauth2-protocol)
+/// or OAUTHBEARER (RFC 7628). A rejected token is answered with an error challenge, JSON such as
+/// `{"status":"401","schemes":"Bearer","scope":"https://mail.google.com/"}`, that must be
+/// acknowledged before the server fails the command; it is kept in `rejection` to explain the failure.
+pub struct BearerAuth {
+    pub mechanism: SaslMechanism,
+    pub user: String,
+    pub access_token: String,
+    pub rejection: Arc<Mutex<Option<String>>>,
+}
+
+impl BearerAuth {
+    pub async fn new(config: &ImapConfig) -> Result<Self> {
+        Ok(BearerAuth {
+            mechanism: oauth2_config(config)?.mechanism,
+            user: config.email.clone(),
+            access_token: access_token(config).await?,
+            rejection: Arc::default(),
+        })
+    }
+
+    pub fn initial_response(&self) -> String {
+        match self.mechanism {
+            SaslMechanism::Xoauth2 => format!("user={}\x01auth=Bearer {}\x01\x01", self.user, self.access_token),
+            SaslMechanism::Oauthbearer => format!("n,a={},\x01auth=Bearer {}\x01\x01", self.user, self.access_token),
+        }
+    }
+}
+
+impl async_imap::Authenticator for BearerAuth {
+    type Response = String;
+
+    fn process(&mut self, challenge: &[u8]) -> Self::Response {
+        if challenge.is_empty() {
+            return self.initial_response();
+        }
+        // async-imap has already decoded the challenge from base64
+        *self.rejection.lock().unwrap() = Some(String::from_utf8_lossy(challenge).into_owned());
+        match self.mechanism {
+            SaslMechanism::Xoauth2 => String::new(),
+            SaslMechanism::Oauthbearer => "\x01".to_string(),
+        }
+    }
+}
+
+/// Each account of a config file gets its own cache.
+fn token_path(config: &ImapConfig) -> PathBuf {
+    match &config.account {
+        Some(account) => config.config_path.with_file_name(format!("oauth_token-{}.json", crate::resume::mailbox_slug(account))),
+        None => config.config_path.with_file_name(TOKEN_FILE),
+    }
+}
+
+fn oauth2_config(config: &ImapConfig) -> Result<&OAuth2Config> {
+    config.oauth2.as_ref()
+        .ok_or_else(|| anyhow!("auth = \"oauth2\" requires an [oauth2] section with client_id"))
+        .context(ErrorCode::Config)
+}
+
+fn load_token(config: &ImapConfig) -> Option<CachedToken> {
+    let mut token: CachedToken = serde_json::from_str(&std::fs::read_to_string(token_path(config)).ok()?).ok()?;
+    if token.email != config.email {
+        return None;
+    }
+
+    if token.refresh_token.is_none() {
+        token.refresh_token = credentials::refresh_token_entry(config)
+            .and_then(|entry| credentials::read_entry(&entry))
+            .ok()
+            .flatten();
+    }
+    Some(token)
+}
+
+/// The long-lived refresh token goes to the OS keychain when one is available, so the cache
+/// file only holds the short-lived access token. Older caches are migrated on their next refresh.
+fn save_token(config: &ImapConfig, token: &CachedToken) -> Result<()> {
+    let stored_in_keyring = token.refresh_token.as_ref().is_some_and(|refresh_token| {
+        credentials::refresh_token_entry(config)
+            .and_then(|entry| Ok(entry.set_password(refresh_token)?))
+            .is_ok()
+    });
+    let cached = CachedToken {
+        email: token.email.clone(),
+        access_token: token.access_token.clone(),
+        refresh_token: if stored_in_keyring { None } else { token.refresh_token.clone() },
+        expires_at: token.expires_at,
+    };
+
+    let path = token_path(config);
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(&cached)?)?;
+    std::fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+async fn request_token(oauth2: &OAuth2Config, params: &[(&str, &str)]) -> Result<TokenResponse> {
+    let mut form = params.to_vec();
+    form.push(("client_id", &oauth2.client_id));
+    if let Some(client_secret) = &oauth2.client_secret {
+        form.push(("client_secret", client_secret));
+    }
+
+    let response = reqwest::Client::new().post(&oauth2.token_url).form(&form).send().await?;
+    if !response.status().is_success() {
+        bail!("Token request failed: {}", response.text().await.unwrap_or_default());
+    }
+    Ok(response.json().await?)
+}
+
+fn store_response(config: &ImapConfig, response: TokenResponse, previous_refresh_token: Option<String>) -> Result<CachedToken> {
+    let token = CachedToken {
+        email: config.email.clone(),
+        access_token: response.access_token,
+        // Refresh responses usually omit the refresh token, which then stays valid
+        refresh_token: response.refresh_token.or(previous_refresh_token),
+        expires_at: Utc::now() + Duration::seconds(response.expires_in),
+    };
+    save_token(config, &token)?;
+    Ok(token)
+}
+
+/// Waits for the browser to be redirected to the loopback listener and returns the query parameters.
+async fn receive_redirect(listener: &TcpListener) -> Result<Url> {
+    let (mut stream, _) = listener.accept().await?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nAuthorization received, you can close this window.").await?;
+
+    Ok(Url::parse("http://127.0.0.1")?.join(target)?)
+}
+
+/// Runs the authorization code flow with PKCE over a loopback redirect.
+async fn authorize_in_browser(config: &ImapConfig, oauth2: &OAuth2Config) -> Result<CachedToken> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+    let verifier = random_string();
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let state = random_string();
+
+    let auth_url = Url::parse_with_params(&oauth2.auth_url, &[
+        ("response_type", "code"),
+        ("client_id", oauth2.client_id.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("scope", oauth2.scope.as_str()),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+        ("state", state.as_str()),
+        ("login_hint", config.email.as_str()),
+        // Without these Google only returns a refresh token the very first time
+        ("access_type", "offline"),
+        ("prompt", "consent"),
+    ])?;
+
+    info!("-- Open this URL in a browser to authorize access to {}:", config.email);
+    info!("{}", auth_url);
+
+    let redirect = receive_redirect(&listener).await?;
+    let param = |name: &str| redirect.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned());
+
+    if let Some(error) = param("error") {
+        bail!("Authorization was denied: {}", error);
+    }
+    if param("state").as_deref() != Some(state.as_str()) {
+        bail!("Authorization response does not belong to this request");
+    }
+    let code = param("code").ok_or_else(|| anyhow!("Authorization response has no code"))?;
+
+    let response = request_token(oauth2, &[
+        ("grant_type", "authorization_code"),
+        ("code", &code),
+        ("redirect_uri", &redirect_uri),
+        ("code_verifier", &verifier),
+    ]).await?;
+
+    info!("-- Authorized, tokens cached in {:?}", token_path(config));
+    store_response(config, response, None)
+}
+
+async fn refresh(config: &ImapConfig, oauth2: &OAuth2Config, refresh_token: String) -> Result<CachedToken> {
+    let response = request_token(oauth2, &[
+        ("grant_type", "refresh_token"),
+        ("refresh_token", &refresh_token),
+    ]).await?;
+    store_response(config, response, Some(refresh_token))
+}
+
+/// Makes sure a usable token is cached, running the browser flow if needed and allowed.
+pub async fn authorize(config: &ImapConfig, no_prompt: bool) -> Result<()> {
+    let oauth2 = oauth2_config(config)?;
+
+    match load_token(config) {
+        Some(token) if token.expires_at > Utc::now() + Duration::minutes(1) => Ok(()),
+        Some(CachedToken { refresh_token: Some(refresh_token), .. }) => refresh(config, oauth2, refresh_token).await.map(|_| ()),
+        _ if no_prompt => Err(anyhow!("No OAuth2 token cached for {}; run once interactively to authorize", config.email))
+            .context(ErrorCode::Auth),
+        _ => authorize_in_browser(config, oauth2).await.map(|_| ()),
+    }
+}
+
+/// A valid access token from the cache, refreshed when it is about to expire.
+pub async fn access_token(config: &ImapConfig) -> Result<String> {
+    let oauth2 = oauth2_config(config)?;
+
+    match load_token(config) {
+        Some(token) if token.expires_at > Utc::now() + Duration::minutes(1) => Ok(token.access_token),
+        Some(CachedToken { refresh_token: Some(refresh_token), .. }) => {
+            Ok(refresh(config, oauth2, refresh_token).await?.access_token)
+        }
+        _ => Err(anyhow!("No OAuth2 token cached for {}; run once interactively to authorize", config.email))
+            .context(ErrorCode::Auth),
+    }
+}
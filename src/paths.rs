@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+
+use crate::CONFIG_FILE;
+
+/// Folder name below the platform's config and data directories.
+const APP_DIR: &str = "gmail-file-downloader";
+
+/// `$XDG_CONFIG_HOME/gmail-file-downloader` (usually `~/.config/...`) on Linux,
+/// `~/Library/Application Support/...` on macOS and `%APPDATA%\...` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// `$XDG_DATA_HOME/gmail-file-downloader` (usually `~/.local/share/...`) on Linux,
+/// `~/Library/Application Support/...` on macOS and `%APPDATA%\...` on Windows.
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// The configuration file to use: `--config` if given, then a `config.toml` in the current
+/// directory, where earlier versions kept it, then `config.toml` in the platform's config directory.
+pub fn config_file(explicit: Option<&Path>) -> PathBuf {
+    if let Some(path) = explicit {
+        return path.to_path_buf();
+    }
+    let legacy = PathBuf::from(CONFIG_FILE);
+    if legacy.exists() {
+        return legacy;
+    }
+    config_dir().map_or(legacy, |dir| dir.join(CONFIG_FILE))
+}
+
+/// Default `state_dir`: the platform's data directory, unless the current directory already
+/// holds state from a version that kept it there, so existing setups keep their history.
+pub fn state_dir() -> PathBuf {
+    let legacy = ["state.sqlite", "history.jsonl"].iter().any(|file| Path::new(file).exists());
+    match data_dir() {
+        Some(dir) if !legacy => dir,
+        _ => PathBuf::from("."),
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::Cursor;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use exif::{In, Reader, Tag, Value};
+
+use crate::template;
+
+/// Extensions of the photo formats whose EXIF data is read.
+const PHOTO_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "heic", "heif"];
+
+fn ascii_field(exif: &exif::Exif, tag: Tag) -> Option<Vec<u8>> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().cloned(),
+        _ => None,
+    }
+}
+
+/// When the photo was taken and the camera that took it, as far as its EXIF data tells.
+fn read_exif(data: &[u8]) -> (Option<NaiveDateTime>, Option<String>) {
+    let Ok(exif) = Reader::new().read_from_container(&mut Cursor::new(data)) else {
+        return (None, None);
+    };
+
+    let taken = ascii_field(&exif, Tag::DateTimeOriginal)
+        .and_then(|value| exif::DateTime::from_ascii(&value).ok())
+        .and_then(|taken| {
+            NaiveDate::from_ymd_opt(taken.year.into(), taken.month.into(), taken.day.into())?
+                .and_hms_opt(taken.hour.into(), taken.minute.into(), taken.second.into())
+        });
+    let model = ascii_field(&exif, Tag::Model)
+        .map(|value| template::slugify(&String::from_utf8_lossy(&value)))
+        .filter(|model| !model.is_empty());
+
+    (taken, model)
+}
+
+/// New relative path for a JPEG or HEIC photo, `YYYY/MM/DD/YYYYMMDD_HHMMSS_camera-model_hash.jpg`
+/// below the folder `path` is already in, dated by EXIF DateTimeOriginal or else by the email in
+/// local time. `hash` tells apart burst shots and photos sharing the email's date. Other files,
+/// and photos without either date, keep their path.
+pub fn organized_path(path: &str, data: &[u8], email_date: Option<DateTime<Utc>>, hash: &str) -> Option<String> {
+    let (dir, filename) = match path.rsplit_once('/') {
+        Some((dir, filename)) => (Some(dir), filename),
+        None => (None, path),
+    };
+    let (_, extension) = filename.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+    if !PHOTO_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+
+    let (taken, model) = read_exif(data);
+    let taken = taken.or_else(|| email_date.map(|date| date.with_timezone(&Local).naive_local()))?;
+
+    let stem = match model {
+        Some(model) => format!("{}_{}_{}", taken.format("%Y%m%d_%H%M%S"), model, hash),
+        None => format!("{}_{}", taken.format("%Y%m%d_%H%M%S"), hash),
+    };
+    let organized = format!("{}/{}.{}", taken.format("%Y/%m/%d"), stem, extension);
+    Some(match dir {
+        Some(dir) => format!("{}/{}", dir, organized),
+        None => organized,
+    })
+}
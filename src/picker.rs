@@ -0,0 +1,67 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use anyhow::Result;
+use dialoguer::MultiSelect;
+
+use crate::dry_run::{self, Row};
+use crate::estimate::format_size;
+use crate::logging::info;
+use crate::{download_attachments, retry, ImapConfig};
+
+/// Attachments picked for download: mailbox, then UID, then the attachment names of that email.
+pub type Selection = HashMap<String, HashMap<u64, HashSet<String>>>;
+
+/// Shortens `text` to `width` characters, padding shorter text so columns line up.
+fn fit(text: &str, width: usize) -> String {
+    let chars: Vec<char> = text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    if chars.len() > width {
+        chars[..width - 1].iter().chain(['…'].iter()).collect()
+    } else {
+        format!("{:<width$}", chars.iter().collect::<String>(), width = width)
+    }
+}
+
+fn describe(row: &Row) -> String {
+    format!("{}  {}  {}  {}  {:>9}", row.date, fit(&row.sender, 28), fit(&row.subject, 36), fit(&row.filename, 32), format_size(row.size))
+}
+
+/// Lists the matching attachments, found through ENVELOPE and BODYSTRUCTURE only, lets the user
+/// tick the ones to download and then downloads just those.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    let mut imap_session = retry::connect(config).await?;
+    let folders = dry_run::collect(&mut imap_session, config).await?;
+    imap_session.logout().await?;
+
+    let rows: Vec<(&str, &Row)> = folders.iter()
+        .flat_map(|folder| folder.rows.iter().map(|row| (folder.mailbox.as_str(), row)))
+        .collect();
+    if rows.is_empty() {
+        info!("-- No attachments match");
+        return Ok(());
+    }
+
+    let items: Vec<String> = rows.iter().map(|(_, row)| describe(row)).collect();
+    let picked = MultiSelect::new()
+        .with_prompt(format!("{} attachments match; space selects, a toggles all, enter downloads", rows.len()))
+        .items(&items)
+        .max_length(20)
+        .interact()?;
+    if picked.is_empty() {
+        info!("-- Nothing selected");
+        return Ok(());
+    }
+
+    let mut selection = Selection::new();
+    let mut size = 0;
+    for &index in &picked {
+        let (mailbox, row) = rows[index];
+        selection.entry(mailbox.to_string()).or_default()
+            .entry(row.uid.into()).or_default()
+            .insert(row.filename.clone());
+        size += row.size;
+    }
+    info!("-- Downloading {} attachments, ~{}", picked.len(), format_size(size));
+
+    let config = ImapConfig { selection: Some(Arc::new(selection)), ..config.clone() };
+    download_attachments(&config).await
+}
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Context, Result};
+use async_imap::Authenticator;
+use async_std::net::TcpStream;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use mailparse::MailHeaderMap;
+use sha2::{Digest, Sha256};
+
+use crate::errors::ErrorCode;
+use crate::logging::{self, info, status, Status};
+use crate::resume::SeenMessages;
+use crate::shutdown;
+use crate::transport::{self, ImapStream, Security};
+use crate::{oauth, open_tcp, process_message, AuthMethod, Direction, FetchedMessage, ImapConfig, MessageSource};
+
+/// POP3 has no folders; messages are reported as coming from the inbox.
+const MAILBOX: &str = "INBOX";
+
+/// Minimal POP3 client (RFC 1939): enough to list the maildrop and download messages.
+struct Pop3Session<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Pop3Session<S> {
+    fn new(stream: S) -> Self {
+        Pop3Session { stream: BufReader::new(stream) }
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line).await? == 0 {
+            return Err(anyhow!("The POP3 server closed the connection").context(ErrorCode::Connect));
+        }
+        Ok(line)
+    }
+
+    /// Reads a status line, returning the text after `+OK` or failing on `-ERR`.
+    async fn response(&mut self) -> Result<String> {
+        let line = self.read_line().await?;
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        match line.strip_prefix("+OK") {
+            Some(text) => Ok(text.trim().to_string()),
+            None => Err(anyhow!("POP3 server answered {:?}", line)),
+        }
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        let writer = self.stream.get_mut();
+        writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn command(&mut self, command: &str) -> Result<String> {
+        self.send(command).await?;
+        self.response().await
+    }
+
+    /// Reads a multi-line response up to the terminating `.` line, undoing the dot-stuffing.
+    async fn multiline(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(data);
+            }
+            match line.strip_prefix(b".") {
+                Some(unstuffed) => data.extend_from_slice(unstuffed),
+                None => data.extend_from_slice(&line),
+            }
+        }
+    }
+
+    async fn login(&mut self, config: &ImapConfig) -> Result<()> {
+        match config.auth {
+            AuthMethod::Password => {
+                self.command(&format!("USER {}", config.email)).await?;
+                self.command(&format!("PASS {}", config.password.as_deref().unwrap_or_default())).await?;
+            }
+            AuthMethod::Oauth2 => {
+                let mut authenticator = oauth::BearerAuth::new(config).await?;
+                let mechanism = authenticator.mechanism.name();
+                self.send(&format!("AUTH {}", mechanism)).await?;
+                let challenge = self.read_line().await?;
+                if !challenge.starts_with(b"+") {
+                    return Err(anyhow!("POP3 server refused {}: {}", mechanism, String::from_utf8_lossy(&challenge).trim_end()));
+                }
+                self.send(&STANDARD.encode(authenticator.initial_response())).await?;
+
+                // A rejected token comes back as a `+` challenge that has to be answered before the -ERR
+                let line = self.read_line().await?;
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                if line.starts_with("+OK") {
+                    return Ok(());
+                }
+                let Some(challenge) = line.strip_prefix('+') else {
+                    return Err(anyhow!("POP3 server answered {:?}", line).context(ErrorCode::Auth));
+                };
+                let challenge = STANDARD.decode(challenge.trim()).unwrap_or_else(|_| challenge.trim().as_bytes().to_vec());
+                let reply = authenticator.process(&challenge);
+                let reason = authenticator.rejection.lock().unwrap().take();
+                if let Err(e) = self.command(&STANDARD.encode(reply)).await {
+                    let e = match reason {
+                        Some(reason) => e.context(format!("access token rejected: {}", reason)),
+                        None => e,
+                    };
+                    return Err(e.context(ErrorCode::Auth));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Message numbers of the maildrop with their unique ids, which stay the same across sessions.
+    async fn uidl(&mut self) -> Result<Vec<(u32, String)>> {
+        self.command("UIDL").await?;
+        let listing = self.multiline().await?;
+
+        Ok(String::from_utf8_lossy(&listing)
+            .lines()
+            .filter_map(|line| {
+                let (number, uid) = line.trim().split_once(' ')?;
+                Some((number.parse().ok()?, uid.to_string()))
+            })
+            .collect())
+    }
+
+    async fn retr(&mut self, number: u32) -> Result<Vec<u8>> {
+        self.command(&format!("RETR {}", number)).await?;
+        self.multiline().await
+    }
+
+    async fn quit(&mut self) -> Result<()> {
+        self.command("QUIT").await?;
+        Ok(())
+    }
+}
+
+async fn tls_connect(config: &ImapConfig, tcp_stream: TcpStream) -> Result<ImapStream> {
+    let tls = async_native_tls::TlsConnector::new();
+    Ok(ImapStream::Tls(tls.connect(config.server.as_str(), tcp_stream).await.context(ErrorCode::Tls)?))
+}
+
+/// Connects, reads the greeting (upgrading with STLS first if asked to) and logs in.
+async fn connect(config: &ImapConfig) -> Result<Pop3Session<ImapStream>> {
+    let tcp_stream = open_tcp(config).await?;
+
+    let mut session = match config.security {
+        Security::Tls => {
+            let mut session = Pop3Session::new(tls_connect(config, tcp_stream).await?);
+            session.response().await.context(ErrorCode::Connect)?;
+            session
+        }
+        Security::Starttls => {
+            let mut plain = Pop3Session::new(tcp_stream);
+            plain.response().await.context(ErrorCode::Connect)?;
+            plain.command("STLS").await.context(ErrorCode::Tls)?;
+            Pop3Session::new(tls_connect(config, plain.stream.into_inner()).await?)
+        }
+        Security::None if transport::is_loopback(&config.server) => {
+            let mut session = Pop3Session::new(ImapStream::Plain(tcp_stream));
+            session.response().await.context(ErrorCode::Connect)?;
+            session
+        }
+        Security::None => {
+            return Err(anyhow!("security = \"none\" is only allowed for servers on localhost, not {}", config.server)
+                .context(ErrorCode::Config));
+        }
+    };
+    info!("-- Connected to {}:{}", config.server, config.port());
+
+    session.login(config).await.context(ErrorCode::Auth)?;
+    info!("-- Logged in as {}", config.email);
+    Ok(session)
+}
+
+/// The server cannot search, so `sender`, `direction`, `since` and `before` are checked on the headers.
+fn is_wanted(config: &ImapConfig, headers: &[mailparse::MailHeader<'_>]) -> bool {
+    let header = |name| headers.get_all_values(name).join(", ").to_lowercase();
+    let (from, to) = (header("From"), header("To"));
+    let senders = config.senders();
+    let sender_matches = senders.is_empty() || senders.iter().any(|sender| {
+        let sender = sender.to_lowercase();
+        (config.direction != Direction::To && from.contains(&sender))
+            || (config.direction != Direction::From && to.contains(&sender))
+    });
+
+    let date = crate::message_date(headers).map(|date| date.date_naive());
+    let after_since = config.since.is_none_or(|since| date.is_some_and(|date| date >= since));
+    let before_before = config.before.is_none_or(|before| date.is_some_and(|date| date < before));
+
+    sender_matches && after_since && before_before
+}
+
+/// A number standing in for a UID in file names, reports and state. Message numbers shift as mail
+/// is deleted, so it comes from the UIDL value: the value itself where it is a number, as on
+/// some servers, otherwise the first eight bytes of its SHA-256. SQLite stores signed 64-bit
+/// integers, so the result always stays within `i64::MAX`.
+fn stable_uid(uidl: &str) -> u64 {
+    const MAX: u64 = i64::MAX as u64;
+    uidl.parse().ok().filter(|&number| number <= MAX).unwrap_or_else(|| {
+        let digest = Sha256::digest(uidl.as_bytes());
+        u64::from_be_bytes(digest[..8].try_into().unwrap()) & MAX
+    })
+}
+
+/// Downloads every message not seen in an earlier run and passes those matching the filters to
+/// the same processing as IMAP messages.
+pub async fn sweep(config: &ImapConfig) -> Result<()> {
+    tokio::fs::create_dir_all(&config.download_dir).await?;
+    std::fs::create_dir_all(&config.state_dir)?;
+
+    let mut session = connect(config).await?;
+    let seen_path = SeenMessages::path_for(&config.state_dir, "pop3", &config.email);
+    let mut seen = if config.incremental { SeenMessages::load(&seen_path) } else { SeenMessages::default() };
+
+    let pending: Vec<(u32, String)> = session.uidl().await.context(ErrorCode::Imap)?
+        .into_iter()
+        .filter(|(_, uid)| !seen.seen.contains(uid))
+        .collect();
+    info!("-- {} new emails on the POP3 server", pending.len());
+    logging::progress_start(pending.len() as u64);
+
+    for (i, (number, uid)) in pending.iter().enumerate() {
+        if shutdown::requested() {
+            break;
+        }
+        status!(Status::Progress, "email #{} ({}/{})", number, i + 1, pending.len());
+        let data = session.retr(*number).await.context(ErrorCode::Imap)?;
+
+        let (headers, _) = mailparse::parse_headers(&data).context(ErrorCode::Parse)?;
+        if is_wanted(config, &headers) {
+            let source = MessageSource {
+                mailbox: MAILBOX.to_string(),
+                uid_validity: None,
+                uid: stable_uid(uid),
+                thread_id: None,
+            };
+            process_message(source, FetchedMessage::Full(data), config).await?;
+        }
+
+        seen.seen.insert(uid.clone());
+        seen.save(&seen_path)?;
+        logging::progress_advance(1);
+    }
+
+    logging::progress_finish();
+    if shutdown::requested() {
+        info!("-- Stopped, logging out");
+        session.quit().await?;
+        return Err(shutdown::interrupted());
+    }
+
+    info!("-- All messages processed, logging out");
+    session.quit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_uidls_are_kept() {
+        assert_eq!(stable_uid("1234"), 1234);
+    }
+
+    #[test]
+    fn other_uidls_are_hashed_into_sqlite_range() {
+        for uidl in ["GmailId18c2f0a1b2c3d4e5", "000000015f3a7c2e", "18446744073709551615", "a", ""] {
+            let uid = stable_uid(uidl);
+            assert!(uid <= i64::MAX as u64, "{} gave {}", uidl, uid);
+            assert_eq!(uid, stable_uid(uidl));
+        }
+        assert_ne!(stable_uid("GmailId18c2f0a1b2c3d4e5"), stable_uid("GmailId18c2f0a1b2c3d4e6"));
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+use async_imap::types::{Capability, QuotaResourceName};
+
+use crate::estimate::format_size;
+use crate::logging::{info, status, Status};
+use crate::{folders, retry, AfterDownload, ImapConfig};
+
+/// Special-use attributes (RFC 6154, plus Gmail's `\Important`) worth listing.
+const SPECIAL_USE: &[&str] = &["\\All", "\\Archive", "\\Drafts", "\\Flagged", "\\Important", "\\Junk", "\\Sent", "\\Trash"];
+
+fn capability_name(capability: &Capability) -> String {
+    match capability {
+        Capability::Imap4rev1 => "IMAP4rev1".to_string(),
+        Capability::Auth(mechanism) => format!("AUTH={}", mechanism),
+        Capability::Atom(atom) => atom.to_string(),
+    }
+}
+
+/// Capabilities the configured options use, each with what happens without it.
+fn requirements(config: &ImapConfig) -> Vec<(&'static str, &'static str)> {
+    let mut needed = vec![
+        ("IDLE", "watch mode polls every poll_interval seconds instead of being told about new emails"),
+    ];
+    if config.incremental {
+        needed.push(("CONDSTORE", "incremental runs search every email above the last seen UID instead of only changed ones"));
+    }
+    if config.compress {
+        needed.push(("COMPRESS=DEFLATE", "compress = true has no effect"));
+    }
+    if config.gmail_query.is_some() {
+        needed.push(("X-GM-EXT-1", "gmail_query cannot be searched, so every folder fails"));
+    }
+    if config.mark_label.is_some() {
+        needed.push(("X-GM-EXT-1", "mark_label cannot be set"));
+    }
+    if config.group_by_thread {
+        needed.push(("X-GM-EXT-1", "group_by_thread has no conversation ids to group by"));
+    }
+    if config.dedup_messages {
+        needed.push(("X-GM-EXT-1", "emails found in several folders are matched by Message-ID instead of X-GM-MSGID"));
+    }
+    if matches!(config.after_download, AfterDownload::Move(_)) {
+        needed.push(("MOVE", "after_download copies emails, then deletes the originals instead of moving them in one step"));
+    }
+    if matches!(config.after_download, AfterDownload::Move(_) | AfterDownload::Delete) {
+        needed.push(("UIDPLUS", "after_download cannot delete or copy-and-delete emails and leaves them in place"));
+    }
+    needed
+}
+
+/// Logs in and reports what the server supports: its capabilities, namespaces, quota and
+/// special-use folders, warning about missing capabilities the configuration relies on.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    info!("-- Probing {}:{} as {}", config.server, config.port(), config.email);
+    let mut imap_session = retry::connect(config).await?;
+
+    let capabilities = imap_session.capabilities().await?;
+    let mut names: Vec<String> = capabilities.iter().map(capability_name).collect();
+    names.sort();
+    info!("Capabilities:");
+    for name in &names {
+        info!("  {}", name);
+    }
+
+    info!("Namespaces:");
+    if capabilities.has_str("NAMESPACE") {
+        let response = imap_session.run_command_and_read_response("NAMESPACE").await?;
+        for line in String::from_utf8_lossy(&response).lines().filter(|line| line.starts_with("* NAMESPACE")) {
+            info!("  {}", line.trim_start_matches("* NAMESPACE").trim());
+        }
+    } else {
+        info!("  not supported");
+    }
+
+    info!("Quota of INBOX:");
+    if capabilities.has_str("QUOTA") {
+        match imap_session.get_quota_root("INBOX").await {
+            Ok((_, quotas)) if quotas.iter().all(|quota| quota.resources.is_empty()) => info!("  no limit"),
+            Ok((_, quotas)) => {
+                for quota in &quotas {
+                    for resource in &quota.resources {
+                        match resource.name {
+                            // STORAGE is counted in KiB
+                            QuotaResourceName::Storage => info!("  {} storage: {} of {} used", quota.root_name,
+                                format_size(resource.usage * 1024), format_size(resource.limit * 1024)),
+                            _ => info!("  {} {:?}: {} of {} used", quota.root_name, resource.name, resource.usage, resource.limit),
+                        }
+                    }
+                }
+            }
+            Err(e) => status!(Status::Warning, "GETQUOTAROOT failed: {:#}", e),
+        }
+    } else {
+        info!("  not supported");
+    }
+
+    info!("Special-use folders:");
+    let folders = folders::list(&mut imap_session).await?;
+    let mut special = 0;
+    for folder in &folders {
+        let uses: Vec<&str> = SPECIAL_USE.iter().copied().filter(|attribute| folder.has_attribute(attribute)).collect();
+        if !uses.is_empty() {
+            info!("  {:<12} {}", uses.join(" "), folder.name);
+            special += 1;
+        }
+    }
+    if special == 0 {
+        info!("  none marked; name exclude_folders and folders explicitly");
+    }
+
+    let mut missing = 0;
+    for (capability, consequence) in requirements(config) {
+        if !capabilities.has_str(capability) {
+            status!(Status::Warning, "no {}: {}", capability, consequence);
+            missing += 1;
+        }
+    }
+    if missing == 0 {
+        info!("-- The server supports everything the configuration uses");
+    }
+
+    imap_session.logout().await?;
+    Ok(())
+}
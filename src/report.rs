@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Serialize, Deserialize};
+
+use crate::errors::ErrorCode;
+use crate::estimate::{format_duration, format_size};
+
+#[derive(Serialize, Default)]
+struct Counts {
+    messages: usize,
+    attachments_saved: usize,
+    attachments_skipped: usize,
+    attachments_deduplicated: usize,
+    bytes_saved: u64,
+}
+
+#[derive(Serialize)]
+struct Failure {
+    code: &'static str,
+    message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedFile {
+    pub mailbox: String,
+    pub uid: u64,
+    pub path: PathBuf,
+    pub size: u64,
+    #[serde(default)]
+    pub sha256: String,
+}
+
+/// Messages matching the search in one mailbox, used to tell what appeared or disappeared between runs.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MailboxManifest {
+    pub uid_validity: Option<u32>,
+    pub uids: BTreeSet<u32>,
+}
+
+/// Summary of one run, written as `run-<timestamp>.json` for auditing automated ingestion.
+#[derive(Serialize)]
+struct RunReport {
+    started_at: DateTime<Local>,
+    finished_at: Option<DateTime<Local>>,
+    duration_ms: u128,
+    success: bool,
+    config: serde_json::Value,
+    counts: Counts,
+    failures: Vec<Failure>,
+    mailboxes: BTreeMap<String, MailboxManifest>,
+    files: Vec<SavedFile>,
+}
+
+/// The parts of a written run report that `--diff` compares.
+#[derive(Deserialize)]
+pub struct Manifest {
+    pub started_at: DateTime<Local>,
+    #[serde(default)]
+    pub mailboxes: BTreeMap<String, MailboxManifest>,
+    pub files: Vec<SavedFile>,
+}
+
+static REPORT: Mutex<Option<RunReport>> = Mutex::new(None);
+
+fn with_report(f: impl FnOnce(&mut RunReport)) {
+    if let Some(report) = REPORT.lock().unwrap().as_mut() {
+        f(report);
+    }
+}
+
+/// Starts collecting a report. `config` is stored with its secrets removed.
+/// Credentials, removed from the configuration snapshot, top-level ones also from `[[accounts]]` entries.
+const SECRETS: &[&str] = &["password", "password_encrypted"];
+const SINK_SECRETS: &[&str] = &["access_key", "secret_key", "password"];
+const OAUTH2_SECRETS: &[&str] = &["client_secret"];
+
+fn remove_secrets(fields: &mut serde_json::Map<String, serde_json::Value>) {
+    for key in SECRETS {
+        fields.remove(*key);
+    }
+    for (section, secrets) in [("sink", SINK_SECRETS), ("oauth2", OAUTH2_SECRETS)] {
+        if let Some(section) = fields.get_mut(section).and_then(|section| section.as_object_mut()) {
+            for key in secrets {
+                section.remove(*key);
+            }
+        }
+    }
+}
+
+pub fn start(mut config: serde_json::Value) {
+    if let Some(fields) = config.as_object_mut() {
+        remove_secrets(fields);
+        // The proxy URL may carry a password too, and webhook URLs usually embed a token
+        fields.remove("proxy");
+        fields.remove("webhook_url");
+        if let Some(accounts) = fields.get_mut("accounts").and_then(|accounts| accounts.as_array_mut()) {
+            for account in accounts.iter_mut().filter_map(|account| account.as_object_mut()) {
+                remove_secrets(account);
+                account.remove("proxy");
+                account.remove("webhook_url");
+            }
+        }
+    }
+
+    *REPORT.lock().unwrap() = Some(RunReport {
+        started_at: Local::now(),
+        finished_at: None,
+        duration_ms: 0,
+        success: false,
+        config,
+        counts: Counts::default(),
+        failures: Vec::new(),
+        mailboxes: BTreeMap::new(),
+        files: Vec::new(),
+    });
+}
+
+pub fn message_processed() {
+    with_report(|report| report.counts.messages += 1);
+}
+
+pub fn mailbox_searched(mailbox: &str, uid_validity: Option<u32>, uids: &[u32]) {
+    with_report(|report| {
+        let manifest = report.mailboxes.entry(mailbox.to_string()).or_default();
+        manifest.uid_validity = uid_validity;
+        manifest.uids.extend(uids);
+    });
+}
+
+pub fn attachment_saved(mailbox: &str, uid: u64, path: &Path, size: u64, sha256: &str) {
+    with_report(|report| {
+        report.counts.attachments_saved += 1;
+        report.counts.bytes_saved += size;
+        report.files.push(SavedFile {
+            mailbox: mailbox.to_string(),
+            uid,
+            path: path.to_path_buf(),
+            size,
+            sha256: sha256.to_string(),
+        });
+    });
+}
+
+pub fn attachment_skipped() {
+    with_report(|report| report.counts.attachments_skipped += 1);
+}
+
+pub fn attachment_deduplicated() {
+    with_report(|report| report.counts.attachments_deduplicated += 1);
+}
+
+pub fn failure(code: ErrorCode, message: String) {
+    with_report(|report| report.failures.push(Failure { code: code.as_str(), message }));
+}
+
+/// One-line summary of the run so far, e.g. for the end of the console output.
+pub fn summary() -> Option<String> {
+    let report = REPORT.lock().unwrap();
+    let report = report.as_ref()?;
+    let counts = &report.counts;
+    let elapsed = (Local::now() - report.started_at).to_std().unwrap_or_default();
+    let per_second = counts.bytes_saved as f64 / elapsed.as_secs_f64().max(1.0);
+
+    Some(format!("{} emails processed, {} attachments saved ({}, {}/s), {} skipped, {} duplicates in {}",
+        counts.messages, counts.attachments_saved, format_size(counts.bytes_saved), format_size(per_second as u64),
+        counts.attachments_skipped, counts.attachments_deduplicated, format_duration(elapsed)))
+}
+
+/// Finalizes the report and writes it into `state_dir`, returning the written path.
+pub fn finish(state_dir: &Path, success: bool) -> Result<Option<PathBuf>> {
+    let Some(mut report) = REPORT.lock().unwrap().take() else { return Ok(None) };
+
+    let finished_at = Local::now();
+    report.duration_ms = (finished_at - report.started_at).num_milliseconds().max(0) as u128;
+    report.finished_at = Some(finished_at);
+    report.success = success;
+
+    std::fs::create_dir_all(state_dir)?;
+    let path = state_dir.join(format!("run-{}.json", report.started_at.format("%Y%m%d-%H%M%S")));
+    std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+    Ok(Some(path))
+}
+
+/// Paths of all run reports in `state_dir`, oldest first.
+pub fn list(state_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(state_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with("run-") && name.ends_with(".json") {
+            paths.push(path);
+        }
+    }
+
+    // The timestamp in the name sorts chronologically
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn load_manifest(path: &Path) -> Result<Manifest> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
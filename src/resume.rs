@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Serialize, Deserialize};
+
+/// Turns a mailbox name into something safe to use in a state file name.
+pub fn mailbox_slug(mailbox: &str) -> String {
+    mailbox.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+/// Progress of the current run, written after every batch so a killed run can pick up where it stopped.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RunProgress {
+    pub mailbox: String,
+    pub uid_validity: Option<u32>,
+    pub pending: Vec<u32>,
+    pub completed: BTreeSet<u32>,
+    /// First day of the month being processed when searching month by month.
+    #[serde(default)]
+    pub window: Option<NaiveDate>,
+}
+
+impl RunProgress {
+    pub fn new(mailbox: &str, uid_validity: Option<u32>, pending: Vec<u32>) -> Self {
+        RunProgress {
+            mailbox: mailbox.to_string(),
+            uid_validity,
+            pending,
+            completed: BTreeSet::new(),
+            window: None,
+        }
+    }
+
+    /// Each mailbox gets its own file so a run over several folders resumes each one independently.
+    pub fn path_for(state_dir: &Path, mailbox: &str) -> PathBuf {
+        state_dir.join(format!("resume-{}.toml", mailbox_slug(mailbox)))
+    }
+
+    /// Loads a previous run's progress, ignoring it if it belongs to another mailbox
+    /// or the UIDs were invalidated by the server in the meantime.
+    pub fn load(path: &Path, mailbox: &str, uid_validity: Option<u32>) -> Option<Self> {
+        let content = std::fs::read_to_string(path).ok()?;
+        let progress: RunProgress = toml::from_str(&content).ok()?;
+
+        if progress.mailbox == mailbox && progress.uid_validity == uid_validity {
+            Some(progress)
+        } else {
+            None
+        }
+    }
+
+    pub fn remaining(&self) -> Vec<u32> {
+        self.pending.iter()
+            .copied()
+            .filter(|uid| !self.completed.contains(uid))
+            .collect()
+    }
+
+    /// Writes to a temporary file first so a crash mid-write never leaves a truncated state file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml::to_string(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn clear(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Ids of the messages already handled, for backends that cannot search for new ones by UID.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SeenMessages {
+    pub seen: BTreeSet<String>,
+}
+
+impl SeenMessages {
+    /// One file per backend and account, e.g. `pop3-me_example_com.toml`.
+    pub fn path_for(state_dir: &Path, backend: &str, account: &str) -> PathBuf {
+        state_dir.join(format!("{}-{}.toml", backend, mailbox_slug(account)))
+    }
+
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path).ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes to a temporary file first so a crash mid-write never leaves a truncated state file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml::to_string(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use anyhow::Result;
+
+use crate::errors::{self, ErrorCode};
+use crate::logging::{status, Status};
+use crate::{connect_imap, ImapConfig, ImapSession};
+
+const FIRST_DELAY: Duration = Duration::from_secs(2);
+const MAX_DELAY: Duration = Duration::from_secs(120);
+
+/// Whether an error came from the network rather than from the server rejecting a command,
+/// the credentials or the local disk, so reconnecting and trying again may succeed.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    match errors::classify(error) {
+        ErrorCode::Connect | ErrorCode::Tls | ErrorCode::Timeout => true,
+        ErrorCode::Imap => error.chain().any(|cause| matches!(
+            cause.downcast_ref::<async_imap::error::Error>(),
+            Some(async_imap::error::Error::Io(_) | async_imap::error::Error::ConnectionLost)
+        )),
+        _ => false,
+    }
+}
+
+/// Exponentially growing delay between attempts, giving up after `max_retries` of them.
+pub struct Backoff {
+    attempt: u32,
+    max_retries: u32,
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new(max_retries: u32) -> Self {
+        Backoff { attempt: 0, max_retries, delay: FIRST_DELAY }
+    }
+
+    /// Returns how long to wait before trying again, or `None` when the error is not worth
+    /// retrying or every attempt is used up.
+    pub fn next(&mut self, error: &anyhow::Error) -> Option<Duration> {
+        if self.attempt >= self.max_retries || !is_transient(error) {
+            return None;
+        }
+
+        self.attempt += 1;
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+        Some(delay)
+    }
+
+    pub fn describe(&self) -> String {
+        format!("attempt {}/{}", self.attempt, self.max_retries)
+    }
+}
+
+/// Connects and logs in, retrying network failures with backoff.
+pub async fn connect(config: &ImapConfig) -> Result<ImapSession> {
+    let mut backoff = Backoff::new(config.max_retries);
+
+    loop {
+        let e = match connect_imap(config).await {
+            Ok(imap_session) => return Ok(imap_session),
+            Err(e) => e,
+        };
+        let Some(delay) = backoff.next(&e) else { return Err(e) };
+
+        status!(Status::Warning, "cannot connect to {}: {:#}; retrying in {} s ({})",
+            config.server, e, delay.as_secs(), backoff.describe());
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use dialoguer::Password;
+
+pub const PASSPHRASE_ENV: &str = "GMAIL_DOWNLOADER_MASTER_PASSPHRASE";
+const FORMAT_VERSION: &str = "v1";
+const SALT_LEN: usize = 16;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypts a secret with a key derived from `passphrase` (Argon2id + XChaCha20-Poly1305).
+/// The result is `v1:<salt>:<nonce>:<ciphertext>`, each part base64-encoded.
+pub fn encrypt(secret: &str, passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, secret.as_bytes())
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    Ok(format!("{}:{}:{}:{}", FORMAT_VERSION, STANDARD.encode(salt), STANDARD.encode(nonce), STANDARD.encode(ciphertext)))
+}
+
+pub fn decrypt(encrypted: &str, passphrase: &str) -> Result<String> {
+    let parts: Vec<&str> = encrypted.trim().split(':').collect();
+    let [version, salt, nonce, ciphertext] = parts[..] else {
+        bail!("password_encrypted is malformed");
+    };
+    if version != FORMAT_VERSION {
+        bail!("Unsupported password_encrypted format \"{}\"", version);
+    }
+
+    let salt = STANDARD.decode(salt)?;
+    let nonce = STANDARD.decode(nonce)?;
+    let ciphertext = STANDARD.decode(ciphertext)?;
+    if nonce.len() != 24 {
+        bail!("password_encrypted is malformed");
+    }
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher.decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| anyhow!("Cannot decrypt password_encrypted: wrong master passphrase?"))?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// Takes the master passphrase from the environment, or asks for it unless `no_prompt` is set.
+pub fn master_passphrase(no_prompt: bool, confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    if no_prompt {
+        bail!("The password is encrypted but {} is not set", PASSPHRASE_ENV);
+    }
+
+    let mut prompt = Password::new().with_prompt("Master passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat master passphrase", "Passphrases do not match");
+    }
+    Ok(prompt.interact()?)
+}
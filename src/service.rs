@@ -0,0 +1,122 @@
+use std::ffi::OsString;
+use std::sync::mpsc;
+use std::time::Duration;
+use anyhow::Result;
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::logging::info;
+use crate::{credentials, download_attachments, init_logging, load_config, paths, AuthMethod};
+
+const SERVICE_NAME: &str = "GmailFileDownloader";
+const SERVICE_DISPLAY_NAME: &str = "Gmail File Downloader";
+
+define_windows_service!(ffi_service_main, service_main);
+
+pub fn install() -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    let service_info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![OsString::from("--service")],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+
+    let service = manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Downloads email attachments from the configured sender")?;
+    println!("-- Installed service \"{}\"", SERVICE_NAME);
+    Ok(())
+}
+
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::DELETE)?;
+    service.delete()?;
+    println!("-- Uninstalled service \"{}\"", SERVICE_NAME);
+    Ok(())
+}
+
+/// Hands control to the service control manager. Only returns once the service stops.
+pub fn run() -> Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        eprintln!("Service failed: {:?}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    // Services start in System32, so resolve config.toml next to the executable instead
+    if let Some(exe_dir) = std::env::current_exe()?.parent() {
+        std::env::set_current_dir(exe_dir)?;
+    }
+
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let _ = shutdown_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+    let set_state = |state: ServiceState, controls: ServiceControlAccept| {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: controls,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    set_state(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN)?;
+
+    let mut config = load_config(&paths::config_file(None), true)?;
+    // OAuth2 tokens are read from the cache on every login instead
+    if config.auth == AuthMethod::Password {
+        config.password = Some(credentials::resolve_password(&config, None, true)?);
+    }
+    init_logging(&config, true)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let interval = Duration::from_secs(config.poll_interval);
+
+    loop {
+        if let Err(e) = runtime.block_on(download_attachments(&config)) {
+            info!("Download run failed: {:?}", e);
+        }
+
+        match shutdown_rx.recv_timeout(interval) {
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            _ => break,
+        }
+    }
+
+    set_state(ServiceState::Stopped, ServiceControlAccept::empty())?;
+    Ok(())
+}
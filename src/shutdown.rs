@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+use crate::errors::ErrorCode;
+use crate::logging::{status, Status};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static REQUEST: Notify = Notify::const_new();
+
+/// Asks running downloads to stop fetching; emails already fetched are still saved.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+    REQUEST.notify_waiters();
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Completes once a stop is requested, e.g. to end an IDLE wait early.
+pub async fn wait() {
+    let notified = REQUEST.notified();
+    if !requested() {
+        notified.await;
+    }
+}
+
+/// Withdraws a request once everything stopped, e.g. before starting again with a reloaded config.
+pub fn reset() {
+    REQUESTED.store(false, Ordering::SeqCst);
+}
+
+/// The error a run ends with after stopping on request.
+pub fn interrupted() -> anyhow::Error {
+    anyhow::anyhow!("Stopped before every email was processed; the next run continues where this one stopped")
+        .context(ErrorCode::Interrupted)
+}
+
+#[cfg(unix)]
+async fn interrupt_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn interrupt_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Turns the first Ctrl-C or SIGTERM into a stop request, so the run saves what it fetched,
+/// keeps its resume state and logs out. A second one quits at once.
+pub async fn handle_signals() {
+    if interrupt_signal().await.is_err() {
+        return;
+    }
+    status!(Status::Warning, "interrupted: saving the emails already fetched, then logging out; press Ctrl-C again to quit at once");
+    request();
+
+    if interrupt_signal().await.is_ok() {
+        status!(Status::Warning, "interrupted again, quitting without cleaning up");
+        std::process::exit(ErrorCode::Interrupted.exit_code().into());
+    }
+}
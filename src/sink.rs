@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use reqwest::{Method, StatusCode, Url};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ErrorCode;
+use crate::template;
+
+/// Where saved attachments go.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SinkType {
+    /// Files in `download_dir`.
+    #[default]
+    Local,
+    /// Objects in an S3-compatible bucket (AWS, MinIO, Backblaze B2, ...).
+    S3,
+    /// Files on a WebDAV server such as Nextcloud.
+    Webdav,
+}
+
+/// The `[sink]` table. Attachments keep all filters and naming rules; only the final write differs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SinkConfig {
+    #[serde(rename = "type", default)]
+    pub kind: SinkType,
+    /// S3 endpoint (default `https://s3.<region>.amazonaws.com`) or the WebDAV folder to upload into.
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub bucket: Option<String>,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Address buckets as `<url>/<bucket>` instead of `<bucket>.<host>`, as MinIO usually needs.
+    #[serde(default)]
+    pub path_style: bool,
+    /// S3 access key, or the `AWS_ACCESS_KEY_ID` variable.
+    #[serde(default)]
+    pub access_key: Option<String>,
+    /// S3 secret key, or the `AWS_SECRET_ACCESS_KEY` variable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Object key or remote path; `{filename}` is the path the attachment would get below
+    /// `download_dir`, and every `path_template` placeholder is available too.
+    #[serde(default = "default_key_template")]
+    pub key_template: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_key_template() -> String {
+    "{filename}".to_string()
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            kind: SinkType::default(),
+            url: None,
+            bucket: None,
+            region: default_region(),
+            path_style: false,
+            access_key: None,
+            secret_key: None,
+            username: None,
+            password: None,
+            key_template: default_key_template(),
+        }
+    }
+}
+
+impl SinkConfig {
+    pub fn is_remote(&self) -> bool {
+        self.kind != SinkType::Local
+    }
+
+    /// The key an attachment is uploaded under, with Windows separators turned into `/`.
+    pub fn key(&self, filename: &str, vars: &HashMap<&'static str, String>) -> String {
+        let mut vars = vars.clone();
+        vars.insert("filename", filename.replace('\\', "/"));
+        template::render(&self.key_template, &vars).trim_start_matches('/').to_string()
+    }
+}
+
+/// Percent-encodes everything but unreserved characters, keeping `/` between path segments.
+fn encode_path(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// HMAC-SHA256 (RFC 2104), as AWS signatures chain it four times.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).chain(message.iter().copied()).collect();
+    let inner_hash = Sha256::digest(&inner);
+    let outer: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).chain(inner_hash.iter().copied()).collect();
+    Sha256::digest(&outer).into()
+}
+
+fn check_response(response: reqwest::Response, location: &str) -> Result<()> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let code = if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN { ErrorCode::Auth } else { ErrorCode::Io };
+    Err(anyhow!("Upload to {} failed with {}", location, status).context(code))
+}
+
+/// Uploads one attachment and returns where it went, as a URL.
+pub async fn upload(sink: &SinkConfig, key: &str, data: &[u8]) -> Result<String> {
+    match sink.kind {
+        SinkType::Local => Err(anyhow!("The local sink does not upload")),
+        SinkType::S3 => upload_s3(sink, key, data).await,
+        SinkType::Webdav => upload_webdav(sink, key, data).await,
+    }
+}
+
+/// A PUT Object request signed with AWS Signature Version 4.
+async fn upload_s3(sink: &SinkConfig, key: &str, data: &[u8]) -> Result<String> {
+    let bucket = sink.bucket.as_deref().ok_or_else(|| anyhow!("[sink] type = \"s3\" needs `bucket`").context(ErrorCode::Config))?;
+    let access_key = sink.access_key.clone().or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())
+        .ok_or_else(|| anyhow!("[sink] needs `access_key` or AWS_ACCESS_KEY_ID").context(ErrorCode::Config))?;
+    let secret_key = sink.secret_key.clone().or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+        .ok_or_else(|| anyhow!("[sink] needs `secret_key` or AWS_SECRET_ACCESS_KEY").context(ErrorCode::Config))?;
+
+    let endpoint = sink.url.clone().unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", sink.region));
+    let endpoint = Url::parse(&endpoint).with_context(|| format!("Invalid [sink] url {:?}", endpoint)).context(ErrorCode::Config)?;
+    let endpoint_host = endpoint.host_str().ok_or_else(|| anyhow!("[sink] url has no host").context(ErrorCode::Config))?;
+    let (host, path) = if sink.path_style {
+        (endpoint_host.to_string(), format!("/{}/{}", bucket, encode_path(key)))
+    } else {
+        (format!("{}.{}", bucket, endpoint_host), format!("/{}", encode_path(key)))
+    };
+    let host = match endpoint.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    };
+    let url = format!("{}://{}{}", endpoint.scheme(), host, path);
+
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(&Sha256::digest(data));
+    let scope = format!("{}/{}/s3/aws4_request", date, sink.region);
+
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        path, host, payload_hash, amz_date, signed_headers, payload_hash,
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())),
+    );
+
+    let mut signing_key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    for part in [sink.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature,
+    );
+
+    let response = reqwest::Client::new().put(&url)
+        .header("x-amz-content-sha256", &payload_hash)
+        .header("x-amz-date", &amz_date)
+        .header("Authorization", authorization)
+        .body(data.to_vec())
+        .send().await.context(ErrorCode::Connect)?;
+    check_response(response, &url)?;
+    Ok(format!("s3://{}/{}", bucket, key))
+}
+
+/// Collections already created on the WebDAV server during this run.
+static COLLECTIONS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+async fn upload_webdav(sink: &SinkConfig, key: &str, data: &[u8]) -> Result<String> {
+    let base = sink.url.as_deref().ok_or_else(|| anyhow!("[sink] type = \"webdav\" needs `url`").context(ErrorCode::Config))?;
+    let base = base.trim_end_matches('/');
+    let client = reqwest::Client::new();
+    let request = |method: Method, url: &str| {
+        let request = client.request(method, url);
+        match &sink.username {
+            Some(username) => request.basic_auth(username, sink.password.as_deref()),
+            None => request,
+        }
+    };
+
+    // PUT does not create missing folders, so each parent collection is made first
+    let segments: Vec<&str> = key.split('/').collect();
+    for depth in 1..segments.len() {
+        let collection = format!("{}/{}", base, encode_path(&segments[..depth].join("/")));
+        if COLLECTIONS.lock().unwrap().get_or_insert_with(HashSet::new).contains(&collection) {
+            continue;
+        }
+
+        let response = request(Method::from_bytes(b"MKCOL")?, &collection).send().await.context(ErrorCode::Connect)?;
+        // 405 means the collection already exists
+        if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+            check_response(response, &collection)?;
+        }
+        COLLECTIONS.lock().unwrap().get_or_insert_with(HashSet::new).insert(collection);
+    }
+
+    let url = format!("{}/{}", base, encode_path(key));
+    let response = request(Method::PUT, &url).body(data.to_vec()).send().await.context(ErrorCode::Connect)?;
+    check_response(response, &url)?;
+    Ok(url)
+}
@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use serde::{Serialize, Deserialize};
+
+use crate::resume::mailbox_slug;
+use crate::{ImapConfig, ImapSession};
+
+/// What the last completed run saw of a mailbox, used to only look at what changed since.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FolderState {
+    pub uid_validity: Option<u32>,
+    /// HIGHESTMODSEQ reported by a CONDSTORE server when the last run selected the mailbox.
+    #[serde(default)]
+    pub highest_modseq: Option<u64>,
+    /// Highest UID already searched, so servers without CONDSTORE can skip older emails.
+    #[serde(default)]
+    pub highest_uid: Option<u32>,
+    /// `since` and `before` of that run. Emails outside them were never searched, so the state
+    /// only applies to runs with the same dates.
+    #[serde(default)]
+    pub since: Option<NaiveDate>,
+    #[serde(default)]
+    pub before: Option<NaiveDate>,
+}
+
+impl FolderState {
+    pub fn path_for(state_dir: &Path, mailbox: &str) -> PathBuf {
+        state_dir.join(format!("sync-{}.toml", mailbox_slug(mailbox)))
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        toml::from_str(&std::fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Whether this state can narrow the next search of a run with `config`.
+    pub fn applies_to(&self, config: &ImapConfig, uid_validity: Option<u32>) -> bool {
+        config.incremental && self.uid_validity == uid_validity && self.since == config.since && self.before == config.before
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, toml::to_string(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// UIDs of messages added or modified (flags, Gmail labels, undeletion) after `modseq`.
+/// Unlike a UID watermark this also catches old messages that newly became visible in the mailbox.
+pub async fn changed_since(imap_session: &mut ImapSession, modseq: u64) -> Result<HashSet<u32>> {
+    let mut messages_stream = imap_session.uid_fetch("1:*", format!("(UID) (CHANGEDSINCE {})", modseq)).await?;
+    let mut uids = HashSet::new();
+
+    while let Some(message) = messages_stream.try_next().await? {
+        if let Some(uid) = message.uid {
+            uids.insert(uid);
+        }
+    }
+
+    Ok(uids)
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+const SLUG_MAX_LEN: usize = 50;
+
+/// Lowercase ASCII slug: transliterates non-Latin scripts (`Привіт` -> `privit`),
+/// joins words with `-` and caps the length so it is safe on every filesystem.
+pub fn slugify(text: &str) -> String {
+    let transliterated = deunicode::deunicode(text).to_lowercase();
+    let mut slug = String::with_capacity(transliterated.len());
+
+    for c in transliterated.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.truncate(SLUG_MAX_LEN);
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Replaces `{name}` placeholders with their values; unknown placeholders are left untouched.
+pub fn render(template: &str, vars: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        match after.find('}') {
+            Some(end) => {
+                let name = &after[..end];
+                match vars.get(name) {
+                    Some(value) => result.push_str(value),
+                    None => result.push_str(&rest[start..start + end + 2]),
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Names of the `{name}` placeholders in a template, in order.
+pub fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else { break };
+        names.push(&after[..end]);
+        rest = &after[end + 1..];
+    }
+    names
+}
+
+/// Makes a placeholder value usable as (part of) a single path component.
+fn path_safe(value: &str) -> String {
+    let cleaned: String = value.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    cleaned.trim().to_string()
+}
+
+/// Renders a template such as `{sender}/{year}/{month}/{filename}` into a relative path.
+/// Values can never introduce extra directories, and `.`, `..` and empty components are
+/// dropped so the result always stays inside the download directory.
+pub fn render_path(template: &str, vars: &HashMap<&str, String>) -> String {
+    let safe_vars = vars.iter()
+        .map(|(name, value)| (*name, path_safe(value)))
+        .collect();
+
+    render(template, &safe_vars)
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|component| !component.is_empty() && *component != "." && *component != "..")
+        .collect::<Vec<_>>()
+        .join("/")
+}
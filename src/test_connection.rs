@@ -0,0 +1,58 @@
+use std::future::Future;
+use std::time::Instant;
+use anyhow::Result;
+
+use crate::logging::{info, status, Status};
+use crate::transport::Security;
+use crate::{folders, login, open_tcp, search_uids, tls_handshake, ImapConfig};
+
+/// Runs one stage and reports whether it succeeded and how long it took.
+async fn stage<T>(name: &str, future: impl Future<Output = Result<T>>) -> Result<T> {
+    let started = Instant::now();
+    let result = future.await;
+    let elapsed = started.elapsed().as_millis();
+
+    match &result {
+        Ok(_) => info!("  OK    {:<14} {:>6} ms", name, elapsed),
+        Err(e) => status!(Status::Warning, "{} failed after {} ms: {:#}", name, elapsed, e),
+    }
+
+    result
+}
+
+/// Walks through every step of a download run without fetching any message.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    info!("-- Testing connection to {}:{} as {}", config.server, config.port(), config.email);
+
+    let tcp_stream = stage("connect", open_tcp(config)).await?;
+    let handshake = match config.security {
+        Security::Tls => "TLS handshake",
+        Security::Starttls => "STARTTLS",
+        Security::None => "plaintext",
+    };
+    let tls_stream = stage(handshake, tls_handshake(config, tcp_stream)).await?;
+    let mut imap_session = stage("login", login(config, tls_stream)).await?;
+
+    let folders = stage("list folders", folders::list(&mut imap_session)).await?;
+    let mailbox_name = folders.iter()
+        .find(|folder| folder.has_attribute("\\All"))
+        .map(|folder| folder.name.clone())
+        .unwrap_or_else(|| "INBOX".to_string());
+
+    let mailbox = stage(&format!("select {}", mailbox_name), async {
+        Ok(imap_session.select(&mailbox_name).await?)
+    }).await?;
+    info!("        {} messages in {}", mailbox.exists, mailbox_name);
+
+    let uids = stage("search", async {
+        search_uids(&mut imap_session, config, None, None).await
+    }).await?;
+    info!("        {} matching messages", uids.len());
+
+    stage("logout", async {
+        Ok(imap_session.logout().await?)
+    }).await?;
+
+    info!("-- All stages passed");
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use async_imap::types::Fetch;
+
+/// Reads the Gmail conversation id requested with `X-GM-THRID`.
+pub fn gmail_thread_id(message: &Fetch) -> Option<u64> {
+    message.gmail_thr_id().copied()
+}
+
+fn strip_reply_prefixes(subject: &str) -> &str {
+    let mut subject = subject.trim();
+    loop {
+        let lower = subject.to_lowercase();
+        let prefix = ["re:", "fw:", "fwd:", "aw:", "wg:"].iter().find(|p| lower.starts_with(*p));
+        match prefix {
+            Some(prefix) => subject = subject[prefix.len()..].trim_start(),
+            None => return subject,
+        }
+    }
+}
+
+fn folder_safe(name: &str) -> String {
+    let cleaned: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .take(60)
+        .collect();
+    cleaned.trim().trim_end_matches('.').to_string()
+}
+
+/// Returns the folder for a conversation, creating it if needed. The folder is named after the
+/// subject of the first message seen and suffixed with the thread id, which is what later
+/// messages of the thread are matched on, so replies with an edited subject still land in it.
+pub fn thread_dir(download_dir: &Path, thread_id: u64, subject: &str) -> Result<PathBuf> {
+    let suffix = format!("[{:x}]", thread_id);
+
+    if let Ok(entries) = std::fs::read_dir(download_dir) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().ends_with(&suffix) && entry.path().is_dir() {
+                return Ok(entry.path());
+            }
+        }
+    }
+
+    let title = folder_safe(strip_reply_prefixes(subject));
+    let name = if title.is_empty() { suffix } else { format!("{} {}", title, suffix) };
+    let dir = download_dir.join(name);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
@@ -0,0 +1,134 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ImapConfig;
+
+const THROTTLED_DELAY: Duration = Duration::from_secs(30);
+const OVERQUOTA_DELAY: Duration = Duration::from_secs(300);
+const MAX_DELAY: Duration = Duration::from_secs(1800);
+
+/// Token bucket refilled evenly over a minute, holding at most one minute's worth.
+struct Bucket {
+    per_minute: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.updated).as_secs_f64() * self.per_minute / 60.0;
+        self.available = (self.available + earned).min(self.per_minute);
+        self.updated = now;
+    }
+
+    /// How long until `amount` tokens are available.
+    fn wait_for(&mut self, amount: f64) -> Duration {
+        self.refill();
+        if self.available >= amount {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.available) * 60.0 / self.per_minute)
+        }
+    }
+}
+
+/// Shared by every session of the process, so the pool and watched folders stay under one limit.
+struct Limits {
+    fetches: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+static LIMITS: Mutex<Limits> = Mutex::new(Limits { fetches: None, bytes: None });
+
+/// The bucket for a limit, created on first use and dropped when the limit is unset.
+fn bucket(slot: &mut Option<Bucket>, per_minute: Option<u64>) -> Option<&mut Bucket> {
+    let Some(per_minute) = per_minute.filter(|&limit| limit > 0) else {
+        *slot = None;
+        return None;
+    };
+    let bucket = slot.get_or_insert_with(|| Bucket {
+        per_minute: per_minute as f64,
+        available: per_minute as f64,
+        updated: Instant::now(),
+    });
+    bucket.per_minute = per_minute as f64;
+    Some(bucket)
+}
+
+/// Waits until `max_fetches_per_minute` allows another fetch and the bytes of earlier fetches
+/// are paid off under `max_bytes_per_minute`, then takes the fetch's token.
+pub async fn before_fetch(config: &ImapConfig) {
+    loop {
+        let wait = {
+            let mut limits = LIMITS.lock().unwrap();
+            let limits = &mut *limits;
+            let fetch_wait = bucket(&mut limits.fetches, config.max_fetches_per_minute.map(u64::from))
+                .map_or(Duration::ZERO, |bucket| bucket.wait_for(1.0));
+            // A fetch's size is only known afterwards, so bytes may go into debt that later fetches wait off
+            let byte_wait = bucket(&mut limits.bytes, config.max_bytes_per_minute.map(|size| size.0))
+                .map_or(Duration::ZERO, |bucket| bucket.wait_for(0.0));
+
+            let wait = fetch_wait.max(byte_wait);
+            if wait.is_zero() {
+                if let Some(bucket) = &mut limits.fetches {
+                    bucket.available -= 1.0;
+                }
+                return;
+            }
+            wait
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Charges the bytes of a finished fetch against `max_bytes_per_minute`.
+pub fn fetched(config: &ImapConfig, bytes: usize) {
+    let mut limits = LIMITS.lock().unwrap();
+    if let Some(bucket) = bucket(&mut limits.bytes, config.max_bytes_per_minute.map(|size| size.0)) {
+        bucket.available -= bytes as f64;
+    }
+}
+
+/// Base delay for an error carrying Gmail's `[THROTTLED]` or `[OVERQUOTA]` response code.
+fn throttle_delay(error: &anyhow::Error) -> Option<Duration> {
+    error.chain().find_map(|cause| {
+        let message = cause.to_string();
+        if message.contains("[OVERQUOTA]") {
+            Some(OVERQUOTA_DELAY)
+        } else if message.contains("[THROTTLED]") {
+            Some(THROTTLED_DELAY)
+        } else {
+            None
+        }
+    })
+}
+
+/// Growing delay before fetching again after the server asked the client to slow down.
+pub struct Backoff {
+    attempt: u32,
+    max_retries: u32,
+}
+
+impl Backoff {
+    pub fn new(max_retries: u32) -> Self {
+        Backoff { attempt: 0, max_retries }
+    }
+
+    /// Returns how long to wait, or `None` when the error is not a throttling response or every
+    /// attempt is used up.
+    pub fn next(&mut self, error: &anyhow::Error) -> Option<Duration> {
+        let delay = throttle_delay(error)?;
+        if self.attempt >= self.max_retries {
+            return None;
+        }
+
+        let delay = delay.saturating_mul(2u32.saturating_pow(self.attempt)).min(MAX_DELAY);
+        self.attempt += 1;
+        Some(delay)
+    }
+
+    pub fn describe(&self) -> String {
+        format!("attempt {}/{}", self.attempt, self.max_retries)
+    }
+}
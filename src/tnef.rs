@@ -0,0 +1,195 @@
+use anyhow::{anyhow, Result};
+
+/// First four bytes of every TNEF stream (`winmail.dat`), little-endian 0x223E9F78.
+const SIGNATURE: [u8; 4] = [0x78, 0x9f, 0x3e, 0x22];
+
+const LEVEL_ATTACHMENT: u8 = 2;
+
+/// Attribute IDs (low 16 bits of the attribute word) of the attachment attributes used here.
+const ATT_ATTACH_DATA: u16 = 0x800f;
+const ATT_ATTACH_TITLE: u16 = 0x8010;
+const ATT_ATTACH_REND_DATA: u16 = 0x9002;
+const ATT_ATTACHMENT: u16 = 0x9005;
+
+/// MAPI property holding the attachment's long file name; the title is often cut to 8.3.
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+
+const PT_STRING8: u16 = 0x001e;
+const PT_UNICODE: u16 = 0x001f;
+const PT_BINARY: u16 = 0x0102;
+const PT_OBJECT: u16 = 0x000d;
+const MV_FLAG: u16 = 0x1000;
+
+/// A file Outlook packed into a TNEF container.
+pub struct TnefAttachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+pub fn is_tnef(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow!("TNEF data ends early"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Skips the padding that aligns variable-length MAPI values to four bytes.
+    fn align(&mut self, len: usize) -> Result<()> {
+        self.take((4 - len % 4) % 4)?;
+        Ok(())
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// Cuts text at its first NUL, as TNEF strings end with one or more.
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    let end = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+    String::from_utf16_lossy(&units[..end])
+}
+
+/// Size of a fixed-length MAPI value in a TNEF property list, already padded to four bytes.
+fn fixed_size(kind: u16) -> Option<usize> {
+    match kind {
+        // PT_NULL
+        0x0001 => Some(0),
+        // PT_I2, PT_LONG, PT_FLOAT, PT_ERROR, PT_BOOLEAN
+        0x0002 | 0x0003 | 0x0004 | 0x000a | 0x000b => Some(4),
+        // PT_DOUBLE, PT_CURRENCY, PT_APPTIME, PT_I8, PT_SYSTIME
+        0x0005 | 0x0006 | 0x0007 | 0x0014 | 0x0040 => Some(8),
+        // PT_CLSID
+        0x0048 => Some(16),
+        _ => None,
+    }
+}
+
+/// Looks for `PR_ATTACH_LONG_FILENAME` in the MAPI properties of an `attAttachment` attribute.
+fn long_filename(props: &[u8]) -> Result<Option<String>> {
+    let mut reader = Reader { data: props, pos: 0 };
+    let count = reader.u32()?;
+
+    for _ in 0..count {
+        let kind = reader.u16()?;
+        let id = reader.u16()?;
+        if id >= 0x8000 {
+            // Named property: a GUID, then either a number or a UTF-16 name
+            reader.take(16)?;
+            if reader.u32()? == 0 {
+                reader.u32()?;
+            } else {
+                let len = reader.u32()? as usize;
+                reader.take(len)?;
+                reader.align(len)?;
+            }
+        }
+
+        let base = kind & !MV_FLAG;
+        if let Some(size) = fixed_size(base) {
+            let values = if kind & MV_FLAG != 0 { reader.u32()? as usize } else { 1 };
+            reader.take(size.checked_mul(values).ok_or_else(|| anyhow!("TNEF value count overflows"))?)?;
+            continue;
+        }
+        if !matches!(base, PT_STRING8 | PT_UNICODE | PT_BINARY | PT_OBJECT) {
+            return Err(anyhow!("unknown MAPI property type {:#06x}", kind));
+        }
+
+        // Variable-length values always carry a count, even when not multi-valued
+        let values = reader.u32()?;
+        for _ in 0..values {
+            let len = reader.u32()? as usize;
+            let value = reader.take(len)?;
+            reader.align(len)?;
+            if id == PR_ATTACH_LONG_FILENAME {
+                let name = match base {
+                    PT_UNICODE => utf16(value),
+                    _ => String::from_utf8_lossy(trim_nul(value)).into_owned(),
+                };
+                if !name.is_empty() {
+                    return Ok(Some(name));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Takes the attached files out of a TNEF stream. Each attachment starts with `attAttachRendData`;
+/// its name comes from the long file name property where present, otherwise from `attAttachTitle`.
+/// Files without data, such as embedded messages, are left out.
+pub fn attachments(data: &[u8]) -> Result<Vec<TnefAttachment>> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.take(4)? != SIGNATURE {
+        return Err(anyhow!("not a TNEF stream"));
+    }
+    reader.u16()?; // legacy key
+
+    let mut attachments = Vec::new();
+    let mut current: Option<(Option<String>, Option<Vec<u8>>)> = None;
+    let mut finish = |current: Option<(Option<String>, Option<Vec<u8>>)>| {
+        if let Some((Some(filename), Some(data))) = current {
+            attachments.push(TnefAttachment { filename, data });
+        }
+    };
+
+    while !reader.at_end() {
+        let level = reader.u8()?;
+        let id = (reader.u32()? & 0xffff) as u16;
+        let len = reader.u32()? as usize;
+        let value = reader.take(len)?;
+        reader.u16()?; // checksum
+
+        if level != LEVEL_ATTACHMENT {
+            continue;
+        }
+        match id {
+            ATT_ATTACH_REND_DATA => finish(current.replace((None, None))),
+            ATT_ATTACH_TITLE => {
+                let (filename, _) = current.get_or_insert((None, None));
+                if filename.is_none() {
+                    *filename = Some(String::from_utf8_lossy(trim_nul(value)).into_owned());
+                }
+            }
+            ATT_ATTACH_DATA => current.get_or_insert((None, None)).1 = Some(value.to_vec()),
+            ATT_ATTACHMENT => {
+                if let Ok(Some(name)) = long_filename(value) {
+                    current.get_or_insert((None, None)).0 = Some(name);
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(current);
+
+    Ok(attachments)
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use async_native_tls::TlsStream;
+use async_std::net::TcpStream;
+use futures::io::{AsyncRead, AsyncWrite};
+use serde::{Serialize, Deserialize};
+
+/// How the connection to the server is protected.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Security {
+    /// TLS from the first byte, usually on port 993.
+    #[default]
+    Tls,
+    /// Plaintext connection upgraded with the STARTTLS command, usually on port 143.
+    Starttls,
+    /// No encryption at all; only allowed for servers on the local machine.
+    None,
+}
+
+impl Security {
+    pub fn default_port(self, protocol: Protocol) -> u16 {
+        match (protocol, self) {
+            (Protocol::Imap, Security::Tls) => 993,
+            (Protocol::Imap, Security::Starttls | Security::None) => 143,
+            (Protocol::Pop3, Security::Tls) => 995,
+            (Protocol::Pop3, Security::Starttls | Security::None) => 110,
+            (Protocol::GmailApi, _) => 443,
+        }
+    }
+}
+
+/// Mail protocol spoken with the server.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    #[default]
+    Imap,
+    /// For providers and legacy accounts without IMAP. Only the inbox is available and nothing can be searched on the server.
+    Pop3,
+    /// Gmail's HTTP API with OAuth2 instead of IMAP; `server`, `port` and `security` are not used.
+    GmailApi,
+}
+
+/// Credentials must never cross the network in plaintext, so `security = "none"` is limited to test servers on this machine.
+pub fn is_loopback(server: &str) -> bool {
+    server.eq_ignore_ascii_case("localhost")
+        || server.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Any stream a session can run over, so a deflate layer can be put on top of an `ImapStream`.
+pub trait SessionStream: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug> SessionStream for T {}
+
+/// The connection an IMAP session runs over, encrypted or not, and possibly compressed.
+#[derive(Debug)]
+pub enum ImapStream {
+    Tls(TlsStream<TcpStream>),
+    Plain(TcpStream),
+    /// After `COMPRESS DEFLATE`, wrapping the original stream.
+    Compressed(Box<dyn SessionStream>),
+    /// With `--imap-trace`, logging what passes through the original stream.
+    Traced(Box<TraceStream>),
+}
+
+impl AsyncRead for ImapStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ImapStream::Compressed(stream) => Pin::new(stream).poll_read(cx, buf),
+            ImapStream::Traced(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ImapStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ImapStream::Compressed(stream) => Pin::new(stream).poll_write(cx, buf),
+            ImapStream::Traced(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ImapStream::Compressed(stream) => Pin::new(stream).poll_flush(cx),
+            ImapStream::Traced(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_close(cx),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_close(cx),
+            ImapStream::Compressed(stream) => Pin::new(stream).poll_close(cx),
+            ImapStream::Traced(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
+
+/// Longest part of a line, and most lines of one read or write, that `--imap-trace` logs;
+/// message bodies would otherwise flood the log.
+const TRACE_LINE_LENGTH: usize = 200;
+const TRACE_LINES: usize = 20;
+
+/// Logs the protocol lines passing through a stream, with credentials hidden.
+#[derive(Debug)]
+pub struct TraceStream {
+    inner: ImapStream,
+    /// Between AUTHENTICATE and the tagged response, everything the client sends is a credential.
+    authenticating: bool,
+}
+
+impl TraceStream {
+    pub fn new(inner: ImapStream) -> Self {
+        TraceStream { inner, authenticating: false }
+    }
+
+    /// Replaces the arguments of LOGIN and AUTHENTICATE, and every line sent while authenticating.
+    fn redact(&mut self, line: &str) -> String {
+        let mut words = line.splitn(3, ' ');
+        let (tag, command) = (words.next().unwrap_or_default(), words.next().unwrap_or_default());
+
+        if self.authenticating {
+            "<redacted>".to_string()
+        } else if command.eq_ignore_ascii_case("LOGIN") {
+            format!("{} {} <redacted>", tag, command)
+        } else if command.eq_ignore_ascii_case("AUTHENTICATE") {
+            self.authenticating = true;
+            let mechanism = words.next().unwrap_or_default().split(' ').next().unwrap_or_default();
+            format!("{} {} {} <redacted>", tag, command, mechanism)
+        } else {
+            line.to_string()
+        }
+    }
+
+    fn log(&mut self, sent: bool, data: &[u8]) {
+        let text = String::from_utf8_lossy(data);
+        let lines: Vec<&str> = text.lines().collect();
+
+        for line in lines.iter().take(TRACE_LINES) {
+            let line = if sent {
+                self.redact(line)
+            } else {
+                // Any tagged or untagged response ends the exchange; only `+` continues it
+                if !line.starts_with('+') {
+                    self.authenticating = false;
+                }
+                line.to_string()
+            };
+            let shown: String = line.chars().take(TRACE_LINE_LENGTH).collect();
+            let cut = if shown.len() < line.len() { "..." } else { "" };
+            tracing::trace!(target: crate::logging::IMAP_TRACE, "{} {}{}", if sent { "C:" } else { "S:" }, shown, cut);
+        }
+        if lines.len() > TRACE_LINES {
+            tracing::trace!(target: crate::logging::IMAP_TRACE, "{} ... {} more lines", if sent { "C:" } else { "S:" },
+                lines.len() - TRACE_LINES);
+        }
+    }
+}
+
+impl AsyncRead for TraceStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = result {
+            this.log(false, &buf[..read]);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for TraceStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.log(true, &buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,140 @@
+/// A file embedded in a message body between `begin <mode> <name>` and `end` lines.
+pub struct UuFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+/// Value of one uuencoded character; both space and backtick stand for zero.
+fn sixbit(c: u8) -> u8 {
+    c.wrapping_sub(b' ') & 0x3f
+}
+
+/// Decodes one line: a length character, then four characters for each three bytes.
+/// Trailing spaces that mailers strip are treated as zero.
+fn decode_line(line: &[u8], out: &mut Vec<u8>) {
+    let Some((&count, rest)) = line.split_first() else { return };
+    let count = sixbit(count) as usize;
+    let mut decoded = 0;
+
+    for group in rest.chunks(4) {
+        let value = |i: usize| group.get(i).map_or(0, |&c| sixbit(c));
+        let bytes = [
+            (value(0) << 2) | (value(1) >> 4),
+            (value(1) << 4) | (value(2) >> 2),
+            (value(2) << 6) | value(3),
+        ];
+        let take = (count - decoded).min(3);
+        out.extend_from_slice(&bytes[..take]);
+        decoded += take;
+        if decoded == count {
+            return;
+        }
+    }
+    out.resize(out.len() + count - decoded, 0);
+}
+
+/// The file name of a `begin` line with an octal mode such as `begin 644 photo.jpg`.
+fn begin_line(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("begin ")?;
+    let (mode, name) = rest.split_once(' ')?;
+    if !(3..=4).contains(&mode.len()) || !mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return None;
+    }
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Finds and decodes every uuencoded file in `body`. A block missing its `end` line is still
+/// kept, as the end is often lost when mailers cut long messages.
+pub fn files(body: &[u8]) -> Vec<UuFile> {
+    let mut files = Vec::new();
+    let mut current: Option<UuFile> = None;
+
+    for line in body.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match current.as_mut() {
+            None => {
+                current = std::str::from_utf8(line).ok()
+                    .and_then(begin_line)
+                    .map(|filename| UuFile { filename, data: Vec::new() });
+            }
+            Some(file) => {
+                if line == b"end" {
+                    files.extend(current.take());
+                } else {
+                    decode_line(line, &mut file.data);
+                }
+            }
+        }
+    }
+    files.extend(current.filter(|file| !file.data.is_empty()));
+
+    files
+}
+
+/// Decodes uuencoded lines without a `begin` line around them, as sent with
+/// `Content-Transfer-Encoding: x-uuencode` by some mailers; an `end` line stops decoding.
+pub fn lines(body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for line in body.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line == b"end" {
+            break;
+        }
+        decode_line(line, &mut data);
+    }
+    data
+}
+
+/// Cheap check before decoding, as most bodies contain no uuencoded file.
+pub fn contains_begin(body: &[u8]) -> bool {
+    body.starts_with(b"begin ") || body.windows(7).any(|window| window == b"\nbegin ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(line: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        decode_line(line, &mut out);
+        out
+    }
+
+    #[test]
+    fn decode_line_reads_the_length_character() {
+        assert_eq!(decoded(b"#0V%T"), b"Cat");
+        assert_eq!(decoded(b"\"0V%T"), b"Ca");
+        assert_eq!(decoded(b"!0V%T"), b"C");
+    }
+
+    #[test]
+    fn decode_line_takes_space_and_backtick_as_zero() {
+        assert_eq!(decoded(b"#````"), [0, 0, 0]);
+        assert_eq!(decoded(b"#    "), [0, 0, 0]);
+        assert!(decoded(b"`").is_empty());
+        assert!(decoded(b"").is_empty());
+    }
+
+    #[test]
+    fn decode_line_fills_stripped_trailing_spaces_with_zeros() {
+        // "Ca\0" encodes as "0V$ "; mailers drop the trailing space
+        assert_eq!(decoded(b"#0V$"), [b'C', b'a', 0]);
+        assert_eq!(decoded(b"#"), [0, 0, 0]);
+    }
+
+    #[test]
+    fn files_finds_blocks_with_and_without_end() {
+        let body = b"text\r\nbegin 644 cat.txt\r\n#0V%T\r\n`\r\nend\r\nbegin 600 cut.bin\n!0V%T\n";
+        let files = files(body);
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].filename.as_str(), files[0].data.as_slice()), ("cat.txt", &b"Cat"[..]));
+        assert_eq!((files[1].filename.as_str(), files[1].data.as_slice()), ("cut.bin", &b"C"[..]));
+    }
+
+    #[test]
+    fn begin_needs_an_octal_mode() {
+        assert!(contains_begin(b"hello\nbegin 644 a.txt\n"));
+        assert!(files(b"begin now with this\n#0V%T\nend\n").is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+use crate::errors::ErrorCode;
+use crate::filters::HeaderFilter;
+use crate::logging::{status, Status};
+use crate::oauth::OAuth2Config;
+use crate::proxy::Proxy;
+use crate::sink::{SinkConfig, SinkType};
+use crate::transport::Protocol;
+use crate::{hooks, template, AfterDownload, AuthMethod, FolderRule, ImapConfig, OnExisting, SaveBody, FILENAME_PLACEHOLDERS, PATH_PLACEHOLDERS};
+
+/// Keys accepted besides the field names, through `#[serde(alias)]`.
+const ALIASES: &[&str] = &["backend", "max_attachment_size"];
+
+/// What is wrong with a configuration. Errors stop a run; warnings point at settings without effect.
+#[derive(Default)]
+pub struct Findings {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Findings {
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+}
+
+/// A deserializer that only records the field list a derived `Deserialize` asks for.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs list their fields"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Keys a config struct accepts.
+fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Number of single-character edits between two keys, for "did you mean" hints.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn check_keys(findings: &mut Findings, table: &toml::Table, known: &[&str], prefix: &str) {
+    for key in table.keys() {
+        if known.contains(&key.as_str()) || ALIASES.contains(&key.as_str()) {
+            continue;
+        }
+        let closest = known.iter()
+            .map(|candidate| (distance(key, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min();
+        match closest {
+            Some((_, candidate)) => findings.error(format!("unknown key `{}{}`, did you mean `{}`?", prefix, key, candidate)),
+            None => findings.error(format!("unknown key `{}{}`", prefix, key)),
+        }
+    }
+}
+
+/// Reports keys of the file that no setting reads, which are usually typos silently ignored.
+fn check_unknown_keys(findings: &mut Findings, document: &toml::Table) {
+    let top_level = fields::<ImapConfig>();
+    check_keys(findings, document, top_level, "");
+
+    let nested: [(&str, &[&str]); 2] = [("sink", fields::<SinkConfig>()), ("oauth2", fields::<OAuth2Config>())];
+    for (key, known) in nested {
+        if let Some(table) = document.get(key).and_then(toml::Value::as_table) {
+            check_keys(findings, table, known, &format!("{}.", key));
+        }
+    }
+    if let Some(rules) = document.get("folder_rules").and_then(toml::Value::as_table) {
+        for (folder, rule) in rules {
+            if let Some(rule) = rule.as_table() {
+                check_keys(findings, rule, fields::<FolderRule>(), &format!("folder_rules.\"{}\".", folder));
+            }
+        }
+    }
+    if let Some(filters) = document.get("header_filters").and_then(toml::Value::as_array) {
+        for filter in filters.iter().filter_map(toml::Value::as_table) {
+            check_keys(findings, filter, fields::<HeaderFilter>(), "header_filters.");
+        }
+    }
+    if let Some(accounts) = document.get("accounts").and_then(toml::Value::as_array) {
+        let account_keys: Vec<&str> = top_level.iter().copied().chain(["name"]).collect();
+        for account in accounts.iter().filter_map(toml::Value::as_table) {
+            let name = account.get("name").and_then(toml::Value::as_str).unwrap_or("?");
+            check_keys(findings, account, &account_keys, &format!("accounts.\"{}\".", name));
+        }
+    }
+}
+
+/// Keys of `document` that no setting reads, each with a "did you mean" hint where one is close.
+pub fn unknown_keys(document: &toml::Table) -> Vec<String> {
+    let mut findings = Findings::default();
+    check_unknown_keys(&mut findings, document);
+    findings.errors
+}
+
+/// Placeholders of a template that are not among `known`.
+fn check_template(findings: &mut Findings, option: &str, template: &str, known: &[&str]) {
+    for name in template::placeholders(template) {
+        if !known.contains(&name) {
+            findings.error(format!("{} uses unknown placeholder {{{}}}; known are {}", option, name,
+                known.iter().map(|name| format!("{{{}}}", name)).collect::<Vec<_>>().join(", ")));
+        }
+    }
+}
+
+/// The directory, or the closest existing folder it would be created in, accepts new files.
+fn check_writable(dir: &Path) -> Result<()> {
+    let existing = dir.ancestors()
+        .find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.is_dir())
+        .map(|ancestor| if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor })
+        .ok_or_else(|| anyhow!("no parent folder exists"))?;
+
+    let probe = existing.join(format!(".write-test-{}", std::process::id()));
+    std::fs::write(&probe, b"").with_context(|| format!("cannot create files in {:?}", existing))?;
+    std::fs::remove_file(&probe)?;
+    Ok(())
+}
+
+/// Looks up `server`, which blocks and depends on the network, so only `config validate`
+/// treats a failure as an error. Through a proxy the name is resolved on the proxy's side.
+fn unresolvable(config: &ImapConfig) -> Option<String> {
+    if config.protocol == Protocol::GmailApi || config.proxy.is_some() || config.server.trim().is_empty() {
+        return None;
+    }
+    (config.server.as_str(), config.port()).to_socket_addrs().err()
+        .map(|e| format!("server \"{}\" cannot be resolved: {}", config.server, e))
+}
+
+/// Checks one configuration without logging in. With `document`, the parsed file it was read
+/// from, unknown keys are reported too.
+pub fn check(config: &ImapConfig, document: Option<&toml::Table>) -> Findings {
+    let mut findings = Findings::default();
+    if let Some(document) = document {
+        check_unknown_keys(&mut findings, document);
+    }
+
+    if config.email.trim().is_empty() {
+        findings.error("email is empty".to_string());
+    }
+    if config.protocol != Protocol::GmailApi && config.server.trim().is_empty() {
+        findings.error("server is empty".to_string());
+    }
+    if let Some(proxy) = &config.proxy {
+        if let Err(e) = Proxy::parse(proxy) {
+            findings.error(format!("proxy: {:#}", e));
+        }
+    }
+    if config.auth == AuthMethod::Oauth2 && config.oauth2.is_none() {
+        findings.error("auth = \"oauth2\" needs an [oauth2] section with client_id".to_string());
+    }
+
+    match config.sink.kind {
+        SinkType::Local => {
+            if let Err(e) = check_writable(&config.download_dir) {
+                findings.error(format!("download_dir {:?} is not writable: {:#}", config.download_dir, e));
+            }
+        }
+        SinkType::S3 if config.sink.bucket.is_none() => findings.error("[sink] type = \"s3\" needs bucket".to_string()),
+        SinkType::Webdav if config.sink.url.is_none() => findings.error("[sink] type = \"webdav\" needs url".to_string()),
+        _ => {}
+    }
+    if let Err(e) = check_writable(&config.state_dir) {
+        findings.error(format!("state_dir {:?} is not writable: {:#}", config.state_dir, e));
+    }
+
+    if let Some(template) = &config.filename_template {
+        check_template(&mut findings, "filename_template", template, FILENAME_PLACEHOLDERS);
+    }
+    if let Some(template) = &config.path_template {
+        check_template(&mut findings, "path_template", template, PATH_PLACEHOLDERS);
+    }
+    if config.sink.kind != SinkType::Local {
+        check_template(&mut findings, "[sink] key_template", &config.sink.key_template, PATH_PLACEHOLDERS);
+    }
+    if let Some(command) = &config.on_download {
+        match hooks::split_words(command) {
+            Ok(words) if words.is_empty() => findings.error("on_download is empty".to_string()),
+            // Braces are common in commands (`awk '{print $1}'`), so an unknown name is only suspicious
+            Ok(_) => {
+                for name in template::placeholders(command).into_iter().filter(|name| !hooks::PLACEHOLDERS.contains(name)) {
+                    findings.warning(format!("on_download contains {{{}}}, which is not a placeholder and is passed on as it is", name));
+                }
+            }
+            Err(e) => findings.error(format!("{:#}", e)),
+        }
+    }
+    if let Some(url) = &config.webhook_url {
+        if let Err(e) = reqwest::Url::parse(url) {
+            findings.error(format!("webhook_url is not a valid URL: {}", e));
+        }
+    }
+
+    if let Some(pattern) = &config.subject_regex {
+        if let Err(e) = regex::Regex::new(pattern) {
+            findings.error(format!("subject_regex is not a valid regular expression: {}", e));
+        }
+    }
+    for filter in &config.header_filters {
+        if let Err(e) = regex::Regex::new(&filter.regex) {
+            findings.error(format!("header_filters ({}) is not a valid regular expression: {}", filter.name, e));
+        }
+    }
+
+    if let (Some(min), Some(max)) = (config.min_size, config.max_size) {
+        if min.0 > max.0 {
+            findings.error("min_size is larger than max_size, so every attachment would be skipped".to_string());
+        }
+    }
+    if let (Some(since), Some(before)) = (config.since, config.before) {
+        if since >= before {
+            findings.error(format!("since ({}) is not before before ({}), so no email would be searched", since, before));
+        }
+    }
+    if config.connections == 0 {
+        findings.error("connections must be at least 1".to_string());
+    }
+
+    // Combinations where one setting silently overrides another
+    if config.eml_only {
+        for (set, option) in [
+            (config.save_body != SaveBody::None, "save_body"),
+            (config.extract_archives, "extract_archives"),
+            (!config.include.is_empty() || !config.exclude.is_empty(), "include/exclude"),
+        ] {
+            if set {
+                findings.warning(format!("eml_only saves no attachments or bodies, so {} has no effect", option));
+            }
+        }
+    }
+    if config.fetch_parts && (config.save_eml || config.eml_only || config.save_body != SaveBody::None) {
+        findings.warning("save_eml, eml_only and save_body need the whole message, so fetch_parts is ignored".to_string());
+    }
+    if !config.extract_archives && (config.extract_tar_gz || config.delete_extracted_archives) {
+        findings.warning("extract_tar_gz and delete_extracted_archives only apply with extract_archives = true".to_string());
+    }
+    if config.include_inline_cid && !config.attachments_only {
+        findings.warning("include_inline_cid only applies with attachments_only = true".to_string());
+    }
+    if config.after_download != AfterDownload::None && config.protocol != Protocol::Imap {
+        findings.warning("after_download only applies to IMAP".to_string());
+    }
+    if config.skip_marked && config.mark_flag.is_none() && config.mark_label.is_none() {
+        findings.warning("skip_marked needs mark_flag or mark_label".to_string());
+    }
+    if config.sink.kind != SinkType::Local
+        && (config.on_existing != OnExisting::default() || config.preserve_email_date || config.write_xattrs || config.extract_archives) {
+        findings.warning("on_existing, preserve_email_date, write_xattrs and extract_archives do not apply to uploads to a [sink]".to_string());
+    }
+
+    findings
+}
+
+/// Reads the file the configuration came from, if there is one, for the unknown-key check.
+fn read_document(path: &Path) -> Result<Option<toml::Table>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content.parse::<toml::Table>().with_context(|| format!("{:?} is not valid TOML", path))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn into_result(findings: Findings) -> Result<()> {
+    match findings.errors.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("Invalid configuration: {}", findings.errors[0]).context(ErrorCode::Config)),
+        count => Err(anyhow!("Invalid configuration, {} problems:\n  {}", count, findings.errors.join("\n  ")).context(ErrorCode::Config)),
+    }
+}
+
+/// Checks the configuration a run is about to use, printing warnings and failing on errors.
+pub fn at_startup(config: &ImapConfig) -> Result<()> {
+    let document = read_document(&config.config_path)?;
+    let mut findings = check(config, document.as_ref());
+    if config.after_download == AfterDownload::Delete && !config.confirm_delete {
+        findings.error("after_download = \"delete\" removes emails from the server; confirm it with --yes-really-delete".to_string());
+    }
+    // A lookup failing at startup is often a network that is not up yet; connecting retries
+    if let Some(problem) = unresolvable(config) {
+        findings.warning(problem);
+    }
+    for warning in &findings.warnings {
+        status!(Status::Warning, "{}", warning);
+    }
+    into_result(findings)
+}
+
+/// `config validate`: checks the file and every `[[accounts]]` entry, printing every problem.
+pub fn run(path: &Path) -> Result<()> {
+    let document = read_document(path)?.ok_or_else(|| anyhow!("Cannot read {:?}", path).context(ErrorCode::Config))?;
+    let mut config: ImapConfig = toml::Value::Table(document.clone()).try_into()
+        .map_err(|e: toml::de::Error| anyhow!("{:?} is invalid: {}", path, e.message()))
+        .context(ErrorCode::Config)?;
+    config.config_path = path.to_path_buf();
+
+    let mut all = check(&config, Some(&document));
+    all.errors.extend(unresolvable(&config));
+    for name in config.account_names() {
+        match config.for_account(name) {
+            Ok(account) => {
+                let mut findings = check(&account, None);
+                findings.errors.extend(unresolvable(&account));
+                all.errors.extend(findings.errors.into_iter().map(|error| format!("account \"{}\": {}", name, error)));
+                all.warnings.extend(findings.warnings.into_iter().map(|warning| format!("account \"{}\": {}", name, warning)));
+            }
+            Err(e) => all.error(format!("account \"{}\": {:#}", name, e)),
+        }
+    }
+
+    for warning in &all.warnings {
+        println!("warning: {}", warning);
+    }
+    for error in &all.errors {
+        println!("error: {}", error);
+    }
+    if all.errors.is_empty() {
+        println!("{:?} is valid", path);
+    }
+    into_result(all)
+}
@@ -0,0 +1,107 @@
+use std::collections::{BTreeMap, HashSet};
+use anyhow::Result;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::history::{self, HistoryEntry, HISTORY_FILE};
+use crate::logging::{info, status, Status};
+use crate::{connect_imap, db, extract_attachments, message_query, write_atomic, ImapConfig};
+
+enum Problem {
+    Missing,
+    Corrupted,
+}
+
+fn check_entry(entry: &HistoryEntry) -> Option<Problem> {
+    match std::fs::read(&entry.path) {
+        Ok(data) if format!("{:x}", Sha256::digest(&data)) == entry.sha256 => None,
+        Ok(_) => Some(Problem::Corrupted),
+        Err(_) => Some(Problem::Missing),
+    }
+}
+
+/// Every saved file the history or the state database knows, the latest save of each path.
+fn known_files(config: &ImapConfig) -> Result<Vec<HistoryEntry>> {
+    let mut entries = history::load(&config.state_dir.join(HISTORY_FILE))?;
+
+    // Files saved while the history was missing or deleted are still in the database
+    db::open(&config.db_path())?;
+    let in_history: HashSet<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+    let mut from_db = BTreeMap::new();
+    for entry in db::attachments()? {
+        if !in_history.contains(&entry.path) {
+            from_db.insert(entry.path.clone(), entry);
+        }
+    }
+    entries.extend(from_db.into_values());
+    // Uploads to a `[sink]` are recorded by URL and cannot be re-read from here
+    entries.retain(|entry| !entry.path.to_string_lossy().contains("://"));
+    Ok(entries)
+}
+
+/// Re-hashes every file recorded in the history or the state database and optionally re-downloads broken ones.
+pub async fn run(config: &ImapConfig, repair: bool) -> Result<()> {
+    let entries = known_files(config)?;
+    let mut broken: BTreeMap<String, Vec<HistoryEntry>> = BTreeMap::new();
+
+    for entry in &entries {
+        match check_entry(entry) {
+            Some(Problem::Missing) => status!(Status::Warning, "missing {:?}", entry.path),
+            Some(Problem::Corrupted) => status!(Status::Warning, "corrupted {:?}", entry.path),
+            None => continue,
+        }
+        broken.entry(entry.mailbox.clone()).or_default().push(entry.clone());
+    }
+
+    let broken_count: usize = broken.values().map(Vec::len).sum();
+    info!("-- Verified {} files, {} missing or corrupted", entries.len(), broken_count);
+
+    if !repair || broken_count == 0 {
+        return Ok(());
+    }
+
+    let mut imap_session = connect_imap(config).await?;
+    let mut repaired = 0;
+
+    for (mailbox_name, entries) in broken {
+        let mailbox = imap_session.select(&mailbox_name).await?;
+
+        for entry in entries {
+            if mailbox.uid_validity != entry.uid_validity {
+                status!(Status::Warning, "cannot repair {:?}: UIDs of {} changed since download", entry.path, mailbox_name);
+                continue;
+            }
+
+            let mut messages_stream = imap_session.uid_fetch(entry.uid.to_string(), message_query(config)).await?;
+            let mut restored = false;
+
+            while let Some(message) = messages_stream.try_next().await? {
+                let Some(body) = message.body() else { continue };
+                let parsed = mailparse::parse_mail(body)?;
+
+                let original = extract_attachments(&parsed, config)
+                    .into_iter()
+                    .find(|attachment| format!("{:x}", Sha256::digest(&attachment.data)) == entry.sha256);
+
+                if let Some(attachment) = original {
+                    if let Some(parent) = entry.path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    write_atomic(&entry.path, &attachment.data, true).await?;
+                    restored = true;
+                }
+            }
+
+            if restored {
+                status!(Status::Saved, "{:?} (repaired)", entry.path);
+                repaired += 1;
+            } else {
+                status!(Status::Warning, "cannot repair {:?}: attachment not found in message #{}", entry.path, entry.uid);
+            }
+        }
+    }
+
+    info!("-- Repaired {} of {} files, logging out", repaired, broken_count);
+    imap_session.logout().await?;
+    Ok(())
+}
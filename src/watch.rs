@@ -0,0 +1,129 @@
+use std::time::Duration;
+use anyhow::Result;
+
+use crate::errors::{self, ErrorCode};
+use crate::logging::{info, status, Status};
+use crate::resume::RunProgress;
+use crate::shutdown;
+use crate::sync::FolderState;
+use crate::{
+    capabilities, connect_imap, download_attachments, download_folder, process_uids, search_uids, select_folders,
+    ImapConfig, ImapSession,
+};
+
+/// Servers may drop an idle connection after 30 minutes (RFC 2177), so IDLE is renewed before that.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(29 * 60);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+/// UIDVALIDITY and UIDNEXT of a mailbox when it was last looked at.
+type Seen = (Option<u32>, Option<u32>);
+
+/// Downloads everything once, then waits for new emails in every selected folder and downloads them as they arrive.
+/// Each folder gets its own session and its own `folder_rules`, so a busy folder never delays the others.
+pub async fn run(config: &ImapConfig) -> Result<()> {
+    download_attachments(config).await?;
+
+    let mut imap_session = connect_imap(config).await?;
+    let sources = select_folders(&mut imap_session, config).await?;
+    imap_session.logout().await?;
+
+    info!("-- Watching {} folders", sources.len());
+
+    let watchers = sources.iter().map(|mailbox_name| watch_folder(config.for_folder(mailbox_name), mailbox_name));
+    futures::future::try_join_all(watchers).await?;
+    // Watching only ends when asked to stop
+    Err(shutdown::interrupted())
+}
+
+/// Keeps a folder watched across dropped connections, reconnecting with an increasing delay,
+/// until a stop is requested.
+async fn watch_folder(config: ImapConfig, mailbox_name: &str) -> Result<()> {
+    // Starting from where the first sweep searched up to, emails that arrived during it are not missed
+    let mut seen = FolderState::load(&FolderState::path_for(&config.state_dir, mailbox_name))
+        .and_then(|state| Some((state.uid_validity, Some(state.highest_uid?.checked_add(1)?))));
+    let mut delay = Duration::from_secs(5);
+
+    loop {
+        let result = watch_session(&config, mailbox_name, &mut seen, &mut delay).await;
+        if shutdown::requested() {
+            return Ok(());
+        }
+        let Err(e) = result else { continue };
+
+        // Retrying cannot fix rejected credentials
+        if errors::classify(&e) == ErrorCode::Auth {
+            return Err(e);
+        }
+
+        status!(Status::Warning, "lost connection while watching {}: {:#}; reconnecting in {} s",
+            mailbox_name, e, delay.as_secs());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown::wait() => return Ok(()),
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Watches a folder over one connection until it fails or a stop is requested, then logs out.
+/// Emails that arrived while disconnected are picked up on the next connection through `seen`.
+async fn watch_session(config: &ImapConfig, mailbox_name: &str, seen: &mut Option<Seen>, delay: &mut Duration) -> Result<()> {
+    let mut imap_session = connect_imap(config).await?;
+    let idle_supported = capabilities::has(&mut imap_session, config, "IDLE").await?;
+    *delay = Duration::from_secs(5);
+
+    loop {
+        if shutdown::requested() {
+            imap_session.logout().await?;
+            return Ok(());
+        }
+        let mailbox = imap_session.select(mailbox_name).await?;
+
+        match *seen {
+            Some((uid_validity, _)) if mailbox.uid_validity != uid_validity => {
+                info!("-- UIDs of {} were reset by the server, downloading it again", mailbox_name);
+                download_folder(&mut imap_session, config, mailbox_name).await?;
+            }
+            Some((uid_validity, uid_next)) if mailbox.uid_next != uid_next => {
+                let uids = search_uids(&mut imap_session, config, None, uid_next).await?;
+
+                if !uids.is_empty() {
+                    info!("-- {} new emails in {}", uids.len(), mailbox_name);
+                    let resume_path = RunProgress::path_for(&config.state_dir, mailbox_name);
+                    let mut progress = RunProgress::new(mailbox_name, uid_validity, config.order.sort(uids));
+                    process_uids(&mut imap_session, config, mailbox_name, uid_validity, &mut progress, &resume_path).await?;
+                    RunProgress::clear(&resume_path)?;
+                }
+            }
+            _ => {}
+        }
+
+        *seen = Some((mailbox.uid_validity, mailbox.uid_next));
+
+        if idle_supported {
+            imap_session = idle(imap_session).await?;
+        } else {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(config.poll_interval)) => {}
+                _ = shutdown::wait() => {}
+            }
+        }
+    }
+}
+
+/// Waits until the server reports a change in the selected mailbox, `IDLE_TIMEOUT` passes or a stop is requested.
+async fn idle(imap_session: ImapSession) -> Result<ImapSession> {
+    let mut handle = imap_session.idle();
+    handle.init().await?;
+
+    // Dropping the stop source would end the wait immediately, so it is kept until the wait is over
+    let (wait, _stop) = handle.wait_with_timeout(IDLE_TIMEOUT);
+    tokio::select! {
+        result = wait => {
+            result?;
+        }
+        _ = shutdown::wait() => {}
+    }
+
+    Ok(handle.done().await?)
+}
@@ -0,0 +1,28 @@
+use std::path::Path;
+
+use crate::logging::{status, Status};
+
+/// Provenance of a saved file, stored as `user.*` extended attributes.
+pub struct Provenance<'a> {
+    pub message_id: Option<&'a str>,
+    pub sender: Option<&'a str>,
+    pub subject: Option<&'a str>,
+}
+
+/// Best effort: filesystems without xattr support (FAT, many network shares) only get a warning.
+pub fn write(path: &Path, provenance: &Provenance<'_>) {
+    let attributes = [
+        ("user.message_id", provenance.message_id),
+        ("user.sender", provenance.sender),
+        ("user.subject", provenance.subject),
+    ];
+
+    for (name, value) in attributes {
+        let Some(value) = value else { continue };
+
+        if let Err(e) = xattr::set(path, name, value.as_bytes()) {
+            status!(Status::Warning, "cannot set {} on {:?}: {}", name, path, e);
+            return;
+        }
+    }
+}